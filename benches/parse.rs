@@ -0,0 +1,86 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use weechat_command_parser::{Command, ParsedCommandBuf};
+
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn count_allocations<F: FnOnce()>(f: F) -> usize {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    f();
+    ALLOCATIONS.load(Ordering::Relaxed) - before
+}
+
+fn inputs() -> Vec<(&'static str, Command, &'static str)> {
+    vec![
+        (
+            "flat",
+            Command::new("/hello")
+                .flags(&["-foo", "-spam"])
+                .arg("one", true)
+                .arg("two", false),
+            "/hello -foo bar baz",
+        ),
+        (
+            "subcommand",
+            Command::new("/discord").subcommand(
+                Command::new("server")
+                    .flag("-force")
+                    .arg("name", true)
+                    .arg("channel", false),
+            ),
+            "/discord server -force rust general",
+        ),
+        ("bare", Command::new("/nick").arg("name", false), "/nick"),
+    ]
+}
+
+fn parse(c: &mut Criterion) {
+    for (name, command, input) in inputs() {
+        let allocations = count_allocations(|| {
+            black_box(command.parse(input).unwrap());
+        });
+        println!("{}: {} allocations per parse", name, allocations);
+
+        c.bench_function(&format!("parse {}", name), |b| {
+            b.iter(|| command.parse(black_box(input)).unwrap())
+        });
+
+        let tokens: Vec<&str> = input.split(' ').collect();
+        let mut buf = ParsedCommandBuf::new();
+        command.parse_into(&tokens, &mut buf).unwrap();
+        let allocations = count_allocations(|| {
+            black_box(command.parse_into(&tokens, &mut buf).unwrap());
+        });
+        println!("{}: {} allocations per parse_into", name, allocations);
+
+        c.bench_function(&format!("parse_into {}", name), |b| {
+            b.iter(|| {
+                command
+                    .parse_into(black_box(&tokens), &mut buf)
+                    .map(|_| ())
+                    .unwrap()
+            })
+        });
+    }
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
@@ -0,0 +1,147 @@
+use std::ops::Range;
+
+use crate::{Command, Error, ParsedCommand};
+
+/// A parsed command with the location of every part in the input, for editors and linters.
+///
+/// All spans are byte ranges into the input given to [`Command::parse_ast`], quoted tokens
+/// include their quotes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseTree {
+    /// The command, or subcommand, name.
+    pub name: String,
+    /// Where the name appeared, `None` if the input omitted the command name.
+    pub span: Option<Range<usize>>,
+    /// Flags in order of appearance.
+    pub flags: Vec<FlagNode>,
+    /// Args in declaration order. Shared args appear on the command that declared them.
+    pub args: Vec<ArgNode>,
+    /// The matched subcommand.
+    pub subcommand: Option<Box<ParseTree>>,
+}
+
+/// A single occurrence of a flag.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FlagNode {
+    pub name: String,
+    /// The value of a value flag.
+    pub value: Option<String>,
+    /// Covers both the flag and its value.
+    pub span: Range<usize>,
+}
+
+/// A positional arg.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArgNode {
+    /// The declared name of the arg.
+    pub name: String,
+    pub value: String,
+    /// For rest args this covers every captured token.
+    pub span: Range<usize>,
+}
+
+impl Command {
+    /// Parse `input` into a [`ParseTree`].
+    ///
+    /// This accepts the same input as [`parse`](Command::parse) and fails in the same cases.
+    pub fn parse_ast(&self, input: &str) -> Result<ParseTree, Error> {
+        self.parse(input).map(|parsed| ParseTree::from(&parsed))
+    }
+}
+
+impl From<&ParsedCommand> for ParseTree {
+    fn from(parsed: &ParsedCommand) -> Self {
+        ParseTree {
+            name: parsed.command.clone(),
+            span: parsed.command_span.clone(),
+            flags: parsed
+                .flag_occurrences
+                .iter()
+                .map(|flag| FlagNode {
+                    name: flag.name.clone(),
+                    value: flag.value.clone(),
+                    span: flag.span.clone(),
+                })
+                .collect(),
+            args: parsed
+                .args
+                .iter()
+                .filter(|arg| !arg.inherited)
+                .map(|arg| ArgNode {
+                    name: arg.name.clone(),
+                    value: arg.value.clone(),
+                    span: arg.span.clone(),
+                })
+                .collect(),
+            subcommand: parsed
+                .subcommand_match
+                .as_deref()
+                .map(|(_, subcommand)| Box::new(ParseTree::from(subcommand))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ArgNode, FlagNode, ParseTree};
+    use crate::Command;
+
+    #[test]
+    fn parse_ast() {
+        let command = Command::new("/discord")
+            .allow_quotes(true)
+            .flag("-v")
+            .subcommand(
+                Command::new("say")
+                    .value_flag("--to")
+                    .arg("channel", true)
+                    .rest_arg("text", true),
+            );
+
+        let input = r##" /discord say --to bob "#rust"  hi  there"##;
+        assert_eq!(
+            command.parse_ast(input).unwrap(),
+            ParseTree {
+                name: "/discord".into(),
+                span: Some(1..9),
+                flags: Vec::new(),
+                args: Vec::new(),
+                subcommand: Some(Box::new(ParseTree {
+                    name: "say".into(),
+                    span: Some(10..13),
+                    flags: vec![FlagNode {
+                        name: "--to".into(),
+                        value: Some("bob".into()),
+                        span: 14..22,
+                    }],
+                    args: vec![
+                        ArgNode {
+                            name: "channel".into(),
+                            value: "#rust".into(),
+                            span: 23..30,
+                        },
+                        ArgNode {
+                            name: "text".into(),
+                            value: "hi there".into(),
+                            span: 32..41,
+                        },
+                    ],
+                    subcommand: None,
+                })),
+            }
+        );
+    }
+
+    #[test]
+    fn omitted_name() {
+        let tree = Command::new("/hello")
+            .flag("-foo")
+            .arg("one", true)
+            .parse_ast("-foo bar")
+            .unwrap();
+
+        assert_eq!(tree.span, None);
+        assert_eq!(tree.flags[0].span, 0..4);
+        assert_eq!(tree.args[0].span, 5..8);
+    }
+}
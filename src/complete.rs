@@ -0,0 +1,171 @@
+use crate::tokenizer::{self, Token};
+use crate::{ArgRule, Command};
+
+impl Command {
+    /// Find the arg the end of `input` is positioned on, returning its name and hint.
+    ///
+    /// With a trailing space the position is on the next token, otherwise on the token being
+    /// typed. `None` is returned while typing the command name, a flag or a flag value, and when
+    /// there are more tokens than args.
+    pub fn complete(&self, input: &str) -> Option<(&str, Option<&str>)> {
+        let tokens = if self.allow_quotes {
+            tokenizer::tokenize(input).ok()?
+        } else {
+            tokenizer::split(input)
+        };
+
+        let current = if input.is_empty() || input.ends_with(' ') {
+            tokens.len()
+        } else {
+            tokens.len().checked_sub(1)?
+        };
+
+        let (_, start) = self.match_name(&tokens[..current]);
+        if current == 0
+            && tokens
+                .first()
+                .is_some_and(|name| self.name.starts_with(&name.value))
+        {
+            return None;
+        }
+        let rule = self.arg_at(&tokens[start..current], &tokens[current..])?;
+        Some((&rule.name, rule.hint.as_deref()))
+    }
+
+    /// The arg following the tokens in `before`, `after` starts with the token being typed.
+    fn arg_at(&self, before: &[Token], after: &[Token]) -> Option<&ArgRule> {
+        let mut pos = 0;
+        let mut flags_ended = false;
+        if !self.shared_args.is_empty() {
+            (pos, flags_ended) = self.skip_flags(before, pos)?;
+        }
+
+        for rule in &self.shared_args {
+            if pos == before.len() {
+                return Some(rule);
+            }
+
+            let is_subcommand = !matches!(self.find_subcommand(&before[pos..]), Ok(None));
+            if !is_subcommand || rule.required {
+                pos += 1;
+            }
+        }
+
+        if !flags_ended {
+            pos = self.skip_flags(before, pos)?.0;
+        }
+
+        let rest = before.get(pos..).unwrap_or_default();
+        if let Ok(Some((subcommand, _, count))) = self.find_subcommand(rest) {
+            return subcommand.arg_at(&rest[count..], after);
+        }
+
+        let mut remaining = before.len().checked_sub(pos)?;
+        if remaining == 0
+            && after
+                .first()
+                .is_some_and(|arg| !arg.literal && arg.value.starts_with('-'))
+        {
+            return None;
+        }
+
+        for rule in &self.args {
+            if rule.rest || remaining == 0 {
+                return Some(rule);
+            }
+            remaining -= 1;
+        }
+
+        None
+    }
+
+    /// Skip the flags starting at `pos`, returning where they end and whether a `--` ended them.
+    ///
+    /// `None` if a rest flag takes everything after it, or the value of a flag is being typed.
+    fn skip_flags(&self, before: &[Token], mut pos: usize) -> Option<(usize, bool)> {
+        while let Some(Token {
+            value: arg,
+            literal: false,
+            ..
+        }) = before.get(pos)
+        {
+            if arg == "--" {
+                return Some((pos + 1, true));
+            } else if self.flags.contains(arg) || self.split_value_flag(arg).is_some() {
+                pos += 1;
+            } else if self.value_flags.contains(arg) {
+                pos += 2;
+            } else if self.rest_flags.contains(arg) {
+                return None;
+            } else {
+                break;
+            }
+        }
+
+        if pos > before.len() {
+            return None;
+        }
+        Some((pos, false))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Command;
+
+    #[test]
+    fn flags_before_shared_args() {
+        let command = Command::new("/discord")
+            .flag("--verbose")
+            .value_flag("--guild")
+            .shared_arg("server", true)
+            .subcommand(Command::new("join").arg("channel", true));
+
+        assert_eq!(
+            command.complete("/discord --verbose "),
+            Some(("server", None))
+        );
+        assert_eq!(command.complete("/discord --guild "), None);
+        assert_eq!(
+            command.complete("/discord --verbose libera join "),
+            Some(("channel", None))
+        );
+    }
+
+    #[test]
+    fn complete_hints() {
+        let command = Command::new("/msg")
+            .flag("-v")
+            .value_flag("--server")
+            .arg("nick", true)
+            .arg_hint("nick", "nick")
+            .rest_arg("text", false)
+            .subcommand(
+                Command::new("join")
+                    .arg("channel", true)
+                    .arg_hint("channel", "channel"),
+            );
+
+        assert_eq!(command.complete("/ms"), None);
+        assert_eq!(command.complete("/msg "), Some(("nick", Some("nick"))));
+        assert_eq!(command.complete("/msg ali"), Some(("nick", Some("nick"))));
+        assert_eq!(command.complete("/msg -v --server "), None);
+        assert_eq!(
+            command.complete("/msg -v --server libera al"),
+            Some(("nick", Some("nick")))
+        );
+        assert_eq!(
+            command.complete("/msg alice hello th"),
+            Some(("text", None))
+        );
+        assert_eq!(
+            command.complete("/msg join #ru"),
+            Some(("channel", Some("channel")))
+        );
+        assert_eq!(command.complete("/msg join #rust "), None);
+        assert_eq!(
+            command.complete("/msg -v join "),
+            Some(("channel", Some("channel")))
+        );
+    }
+}
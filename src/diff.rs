@@ -0,0 +1,232 @@
+use crate::ParsedCommand;
+
+/// One way two parsed commands differ, see [`ParsedCommand::diff`].
+///
+/// `left` is always the command `diff` is called on and `right` the other one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Difference {
+    /// The commands have different names.
+    Command { left: String, right: String },
+    /// A flag is set on only one side.
+    Flag { flag: String, in_left: bool },
+    /// A value flag was given different values, in order of appearance.
+    FlagValues {
+        flag: String,
+        left: Vec<String>,
+        right: Vec<String>,
+    },
+    /// An arg has different values, `None` where it is missing.
+    Arg {
+        name: String,
+        left: Option<String>,
+        right: Option<String>,
+    },
+    /// The unparsed tokens of an opaque command differ.
+    RawTokens {
+        left: Vec<String>,
+        right: Vec<String>,
+    },
+    /// Different subcommands matched, `None` where none did.
+    Subcommand {
+        left: Option<String>,
+        right: Option<String>,
+    },
+    /// A difference inside the same matched subcommand, `path` holds the subcommand names from
+    /// the outermost one down.
+    InSubcommand {
+        path: Vec<String>,
+        difference: Box<Difference>,
+    },
+}
+
+impl Difference {
+    fn in_subcommand(self, name: &str) -> Self {
+        match self {
+            Difference::InSubcommand {
+                mut path,
+                difference,
+            } => {
+                path.insert(0, name.to_owned());
+                Difference::InSubcommand { path, difference }
+            }
+            difference => Difference::InSubcommand {
+                path: vec![name.to_owned()],
+                difference: Box::new(difference),
+            },
+        }
+    }
+}
+
+impl ParsedCommand {
+    /// Everything that differs between this command and `other`, empty if they are equal.
+    ///
+    /// Flags are reported sorted, value flags and args in order of appearance. Differences inside
+    /// a subcommand matched on both sides come last.
+    pub fn diff(&self, other: &ParsedCommand) -> Vec<Difference> {
+        let mut differences = Vec::new();
+
+        if self.command != other.command {
+            differences.push(Difference::Command {
+                left: self.command.clone(),
+                right: other.command.clone(),
+            });
+        }
+
+        let mut flags: Vec<(&String, bool)> = self
+            .flags
+            .difference(&other.flags)
+            .map(|flag| (flag, true))
+            .chain(
+                other
+                    .flags
+                    .difference(&self.flags)
+                    .map(|flag| (flag, false)),
+            )
+            .collect();
+        flags.sort_unstable();
+        differences.extend(flags.into_iter().map(|(flag, in_left)| Difference::Flag {
+            flag: flag.clone(),
+            in_left,
+        }));
+
+        let mut value_flags: Vec<&String> = Vec::new();
+        for (flag, _) in self.flag_values.iter().chain(&other.flag_values) {
+            if !value_flags.contains(&flag) {
+                value_flags.push(flag);
+            }
+        }
+        for flag in value_flags {
+            let values = |parsed: &ParsedCommand| -> Vec<String> {
+                parsed
+                    .flag_values
+                    .iter()
+                    .filter(|(name, _)| name == flag)
+                    .map(|(_, value)| value.clone())
+                    .collect()
+            };
+            let (left, right) = (values(self), values(other));
+            if left != right {
+                differences.push(Difference::FlagValues {
+                    flag: flag.clone(),
+                    left,
+                    right,
+                });
+            }
+        }
+
+        let mut names: Vec<&String> = Vec::new();
+        for arg in self.args.iter().chain(&other.args) {
+            if !names.contains(&&arg.name) {
+                names.push(&arg.name);
+            }
+        }
+        for name in names {
+            let value = |parsed: &ParsedCommand| {
+                parsed
+                    .args
+                    .iter()
+                    .find(|arg| arg.name == *name)
+                    .map(|arg| arg.value.clone())
+            };
+            let (left, right) = (value(self), value(other));
+            if left != right {
+                differences.push(Difference::Arg {
+                    name: name.clone(),
+                    left,
+                    right,
+                });
+            }
+        }
+
+        if self.raw_tokens != other.raw_tokens {
+            differences.push(Difference::RawTokens {
+                left: self.raw_tokens.clone(),
+                right: other.raw_tokens.clone(),
+            });
+        }
+
+        match (
+            self.subcommand_match.as_deref(),
+            other.subcommand_match.as_deref(),
+        ) {
+            (Some((left, left_parsed)), Some((right, right_parsed))) if left == right => {
+                differences.extend(
+                    left_parsed
+                        .diff(right_parsed)
+                        .into_iter()
+                        .map(|difference| difference.in_subcommand(left)),
+                );
+            }
+            (None, None) => {}
+            (left, right) => differences.push(Difference::Subcommand {
+                left: left.map(|(name, _)| name.clone()),
+                right: right.map(|(name, _)| name.clone()),
+            }),
+        }
+
+        differences
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Command, Difference};
+
+    #[test]
+    fn diff() {
+        let command = Command::new("/window")
+            .flags(&["-quiet", "-all"])
+            .value_flag("-name")
+            .arg("number", false)
+            .subcommand(Command::new("close").flag("-force").arg("number", false))
+            .root_args_when_no_subcommand(true);
+
+        let parse = |input: &str| command.parse(input).unwrap();
+
+        assert!(parse("/window -all 2")
+            .diff(&parse("/window -all 2"))
+            .is_empty());
+        assert_eq!(
+            parse("/window -quiet -name a 2").diff(&parse("/window -all -name b 3")),
+            &[
+                Difference::Flag {
+                    flag: "-all".into(),
+                    in_left: false,
+                },
+                Difference::Flag {
+                    flag: "-quiet".into(),
+                    in_left: true,
+                },
+                Difference::FlagValues {
+                    flag: "-name".into(),
+                    left: vec!["a".into()],
+                    right: vec!["b".into()],
+                },
+                Difference::Arg {
+                    name: "number".into(),
+                    left: Some("2".into()),
+                    right: Some("3".into()),
+                },
+            ]
+        );
+
+        assert_eq!(
+            parse("/window close -force 1").diff(&parse("/window close 1")),
+            &[Difference::InSubcommand {
+                path: vec!["close".into()],
+                difference: Box::new(Difference::Flag {
+                    flag: "-force".into(),
+                    in_left: true,
+                }),
+            }]
+        );
+        assert_eq!(
+            parse("/window close").diff(&parse("/window")),
+            &[Difference::Subcommand {
+                left: Some("close".into()),
+                right: None,
+            }]
+        );
+    }
+}
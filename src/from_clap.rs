@@ -0,0 +1,114 @@
+//! Conversion from `clap` definitions, enabled with the `clap` feature.
+
+use crate::Command;
+
+/// Map a subset of a `clap::Command` into a [`Command`].
+///
+/// Supported are the name, `about` as the description, aliases, subcommands and these args:
+///
+/// - Named args that don't take a value, like `ArgAction::SetTrue` or `ArgAction::Count`, become
+///   flags. Named args that do become value flags.
+/// - Named args use their long name as `--long`, or else their short name as `-s`. Only one of
+///   the two is registered.
+/// - Positional args keep their order and `required` setting. Positionals with
+///   `ArgAction::Append` or more than one value become a rest arg, and possible values become
+///   [`Command::arg_choices`].
+///
+/// Everything else, like defaults, groups, conflicts and global args, is ignored.
+impl From<&clap::Command> for Command {
+    fn from(clap: &clap::Command) -> Self {
+        let mut command = Command::new(clap.get_name());
+
+        if let Some(about) = clap.get_about() {
+            command = command.description(&about.to_string());
+        }
+        for alias in clap.get_aliases() {
+            command = command.alias(alias);
+        }
+
+        for arg in clap.get_arguments().filter(|arg| !arg.is_positional()) {
+            let name = match (arg.get_long(), arg.get_short()) {
+                (Some(long), _) => format!("--{}", long),
+                (None, Some(short)) => format!("-{}", short),
+                (None, None) => continue,
+            };
+
+            command = if arg.get_action().takes_values() {
+                command.value_flag(&name)
+            } else {
+                command.flag(&name)
+            };
+        }
+
+        for arg in clap.get_positionals() {
+            let name = arg.get_id().as_str();
+            let rest = matches!(arg.get_action(), clap::ArgAction::Append)
+                || arg
+                    .get_num_args()
+                    .is_some_and(|range| range.max_values() > 1);
+
+            command = if rest {
+                command.rest_arg(name, arg.is_required_set())
+            } else {
+                command.arg(name, arg.is_required_set())
+            };
+
+            let choices: Vec<String> = arg
+                .get_possible_values()
+                .iter()
+                .map(|value| value.get_name().to_owned())
+                .collect();
+            if !choices.is_empty() {
+                let choices: Vec<&str> = choices.iter().map(String::as_str).collect();
+                command = command.arg_choices(name, &choices);
+            }
+        }
+
+        for subcommand in clap.get_subcommands() {
+            command = command.subcommand(Command::from(subcommand));
+        }
+
+        command
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::{Arg, ArgAction};
+
+    use crate::Command;
+
+    #[test]
+    fn from_clap() {
+        let clap = clap::Command::new("/discord")
+            .about("Discord integration")
+            .arg(Arg::new("verbose").short('v').action(ArgAction::SetTrue))
+            .arg(Arg::new("guild").long("guild"))
+            .subcommand(
+                clap::Command::new("status")
+                    .alias("st")
+                    .arg(
+                        Arg::new("status")
+                            .required(true)
+                            .value_parser(["online", "idle", "dnd"]),
+                    )
+                    .arg(Arg::new("message").num_args(1..)),
+            );
+
+        let command = Command::from(&clap);
+        assert_eq!(command.get_description(), Some("Discord integration"));
+        assert_eq!(command.usage(), "/discord [flags] <subcommand>");
+
+        let matches = command
+            .parse("/discord -v --guild rust st idle back soon")
+            .unwrap();
+        assert!(matches.has_flag("-v"));
+        assert_eq!(matches.flag_value("--guild"), Some("rust"));
+
+        let (name, status) = matches.subcommand().unwrap();
+        assert_eq!(name, "status");
+        assert_eq!(status.arg("status"), Some("idle"));
+        assert_eq!(status.arg("message"), Some("back soon"));
+        assert!(command.parse("/discord status away").is_err());
+    }
+}
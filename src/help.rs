@@ -0,0 +1,309 @@
+use crate::{ArgRule, Command};
+
+/// `<name>` for required args and `[name]` for optional ones, with `...` for rest and greedy args.
+///
+/// Choices or else the hint are added after a colon, as in `<status:online|idle>` or
+/// `[user:nick]`.
+fn arg_usage(arg: &ArgRule) -> String {
+    let dots = if arg.rest || arg.until_flag {
+        "..."
+    } else {
+        ""
+    };
+    let annotation = if !arg.choices.is_empty() {
+        format!(":{}", arg.choices.join("|"))
+    } else if let Some(hint) = &arg.hint {
+        format!(":{}", hint)
+    } else {
+        String::new()
+    };
+
+    if arg.required {
+        format!("<{}{}{}>", arg.name, annotation, dots)
+    } else {
+        format!("[{}{}{}]", arg.name, annotation, dots)
+    }
+}
+
+impl Command {
+    /// A one line usage summary, e.g. `/hello [flags] <one> [two]`.
+    ///
+    /// Required args are shown in angle brackets, optional ones in square brackets.
+    pub fn usage(&self) -> String {
+        let mut usage = self.name.clone();
+
+        if !self.flags.is_empty() || !self.value_flags.is_empty() || !self.rest_flags.is_empty() {
+            usage.push_str(" [flags]");
+        }
+
+        for arg in self.shared_args.iter().chain(&self.args) {
+            usage.push(' ');
+            usage.push_str(&arg_usage(arg));
+        }
+
+        if !self.subcommands.is_empty() {
+            usage.push_str(" <subcommand>");
+        }
+
+        usage
+    }
+
+    /// Multi line help listing the description, usage, flags and subcommands.
+    ///
+    /// Flags are listed under the headings given with [`flag_group`], remaining flags are sorted
+    /// under "Flags".
+    ///
+    /// [`flag_group`]: Command::flag_group
+    pub fn help(&self) -> String {
+        let mut help = String::new();
+        if let Some(description) = &self.description {
+            help.push_str(&format!("{}\n\n", description));
+        }
+        help.push_str(&format!("Usage: {}\n", self.usage()));
+
+        let mut ungrouped: Vec<&String> = self
+            .flags
+            .iter()
+            .chain(&self.value_flags)
+            .chain(&self.rest_flags)
+            .filter(|flag| {
+                !self
+                    .flag_groups
+                    .iter()
+                    .any(|(_, flags)| flags.contains(flag))
+            })
+            .collect();
+        ungrouped.sort();
+
+        for (group, flags) in &self.flag_groups {
+            self.push_flags(&mut help, group, flags.iter());
+        }
+        self.push_flags(&mut help, "Flags", ungrouped.into_iter());
+
+        if !self.subcommands.is_empty() {
+            help.push_str("\nSubcommands:\n");
+            let width = self
+                .subcommands
+                .iter()
+                .map(|subcommand| subcommand.name.len())
+                .max()
+                .unwrap_or(0);
+
+            for subcommand in &self.subcommands {
+                match &subcommand.description {
+                    Some(description) => help.push_str(&format!(
+                        "  {:width$}  {}\n",
+                        subcommand.name,
+                        description,
+                        width = width
+                    )),
+                    None => help.push_str(&format!("  {}\n", subcommand.name)),
+                }
+            }
+        }
+
+        help
+    }
+
+    /// An indented dump of the whole command tree, for debugging command definitions.
+    ///
+    /// Every command lists its flags (sorted), shared args, args and then its subcommands in
+    /// declaration order, indented by two spaces per level. Unlike [`help`](Command::help) this
+    /// is not meant for users; the output is stable so it can be used in snapshot tests.
+    pub fn tree_string(&self) -> String {
+        let mut tree = String::new();
+        self.push_tree(&mut tree, 0);
+        tree
+    }
+
+    fn push_tree(&self, tree: &mut String, depth: usize) {
+        let indent = "  ".repeat(depth);
+
+        tree.push_str(&indent);
+        tree.push_str(&self.name);
+        if self.opaque {
+            tree.push_str(" (opaque)");
+        }
+        tree.push('\n');
+
+        let mut flags: Vec<String> = self
+            .flags
+            .iter()
+            .cloned()
+            .chain(
+                self.value_flags
+                    .iter()
+                    .map(|flag| format!("{} <value>", flag)),
+            )
+            .chain(
+                self.rest_flags
+                    .iter()
+                    .map(|flag| format!("{} <value...>", flag)),
+            )
+            .collect();
+        flags.sort();
+        for flag in flags {
+            tree.push_str(&format!("{}  flag {}\n", indent, flag));
+        }
+
+        for arg in &self.shared_args {
+            tree.push_str(&format!("{}  shared arg {}\n", indent, arg_usage(arg)));
+        }
+        for arg in &self.args {
+            tree.push_str(&format!("{}  arg {}\n", indent, arg_usage(arg)));
+        }
+
+        for subcommand in &self.subcommands {
+            subcommand.push_tree(tree, depth + 1);
+        }
+    }
+
+    fn push_flags<'a>(
+        &self,
+        help: &mut String,
+        heading: &str,
+        flags: impl Iterator<Item = &'a String>,
+    ) {
+        let mut flags = flags.peekable();
+        if flags.peek().is_none() {
+            return;
+        }
+
+        help.push_str(&format!("\n{}:\n", heading));
+        for flag in flags {
+            if self.value_flags.contains(flag) {
+                help.push_str(&format!("  {} <value>\n", flag));
+            } else if self.rest_flags.contains(flag) {
+                help.push_str(&format!("  {} <value...>\n", flag));
+            } else {
+                help.push_str(&format!("  {}\n", flag));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuildError, Command};
+
+    #[test]
+    fn usage() {
+        let command = Command::new("/hello")
+            .flag("-foo")
+            .arg("one", true)
+            .arg("two", false)
+            .subcommand(Command::new("sub"));
+
+        assert_eq!(command.usage(), "/hello [flags] <one> [two] <subcommand>");
+        assert_eq!(Command::new("/bare").usage(), "/bare");
+    }
+
+    #[test]
+    fn annotated_usage() {
+        let command = Command::new("/status")
+            .arg("status", true)
+            .arg("user", false)
+            .rest_arg("message", false)
+            .arg_choices("status", &["online", "idle", "dnd"])
+            .arg_hint("status", "ignored")
+            .arg_hint("user", "nick");
+
+        assert_eq!(
+            command.usage(),
+            "/status <status:online|idle|dnd> [user:nick] [message...]"
+        );
+    }
+
+    #[test]
+    fn flag_groups() {
+        let command = Command::new("/connect")
+            .flags(&["-tls", "-quiet", "-verbose"])
+            .value_flag("-port")
+            .rest_after_flag("-exec")
+            .flag_group("Connection", &["-tls", "-port"])
+            .flag_group("Display", &["-quiet"])
+            .arg("server", true);
+
+        assert!(command.validate().is_ok());
+        assert_eq!(
+            command.help(),
+            "Usage: /connect [flags] <server>\n\
+             \n\
+             Connection:\n  -tls\n  -port <value>\n\
+             \n\
+             Display:\n  -quiet\n\
+             \n\
+             Flags:\n  -exec <value...>\n  -verbose\n"
+        );
+    }
+
+    #[test]
+    fn unregistered_group_flag() {
+        let command = Command::new("/connect")
+            .flag("-tls")
+            .flag_group("Connection", &["-tls", "-port"]);
+
+        assert_eq!(
+            command.validate(),
+            Err(BuildError::UnregisteredGroupFlag {
+                group: "Connection".into(),
+                flag: "-port".into(),
+            })
+        );
+    }
+
+    #[test]
+    fn description() {
+        let command = Command::new("/discord")
+            .description("Manage the Discord connection")
+            .subcommand(Command::new("connect").description("Connect to Discord"))
+            .subcommand(Command::new("me"))
+            .subcommand(Command::new("join").description("Join a channel"));
+
+        assert_eq!(
+            command.get_description(),
+            Some("Manage the Discord connection")
+        );
+        assert_eq!(Command::new("/bare").get_description(), None);
+        assert_eq!(
+            command.help(),
+            "Manage the Discord connection\n\
+             \n\
+             Usage: /discord <subcommand>\n\
+             \n\
+             Subcommands:\n  \
+             connect  Connect to Discord\n  \
+             me\n  \
+             join     Join a channel\n"
+        );
+    }
+
+    #[test]
+    fn tree_string() {
+        let command = Command::new("/discord")
+            .flags(&["-v", "-a"])
+            .value_flag("--to")
+            .shared_arg("server", false)
+            .subcommand(
+                Command::new("say")
+                    .arg("channel", true)
+                    .rest_arg("text", false)
+                    .subcommand(Command::new("nested")),
+            )
+            .opaque_subcommand("exec");
+
+        assert_eq!(
+            command.tree_string(),
+            "/discord\n\
+             \x20 flag --to <value>\n\
+             \x20 flag -a\n\
+             \x20 flag -v\n\
+             \x20 shared arg [server]\n\
+             \x20 say\n\
+             \x20   arg <channel>\n\
+             \x20   arg [text...]\n\
+             \x20   nested\n\
+             \x20 exec (opaque)\n"
+        );
+    }
+}
@@ -1,46 +1,706 @@
+use std::borrow::Cow;
 use std::collections::HashSet;
 use std::fmt::Formatter;
+use std::hash::{Hash, Hasher};
+use std::ops::{Range, RangeInclusive};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 use std::{error, fmt};
 
+pub use ast::{ArgNode, FlagNode, ParseTree};
+pub use diff::Difference;
+pub use spec::Spec;
+use tokenizer::Token;
+pub use typed::FromArgs;
+
+mod ast;
+mod complete;
+mod diff;
+#[cfg(feature = "clap")]
+mod from_clap;
+mod help;
+mod spec;
+mod tokenizer;
+mod typed;
+
+/// An error while parsing a command.
+///
+/// New variants may be added in future releases, so matches need a wildcard arm. The
+/// [`Display`](fmt::Display) message makes a good fallback:
+///
+/// ```
+/// use weechat_command_parser::{Command, Error};
+///
+/// let error = Command::new("/nick").arg("name", true).parse("/nick").unwrap_err();
+/// let message = match &error {
+///     Error::RequiredArgMissing(arg) => format!("Please give a {}", arg),
+///     other => other.to_string(),
+/// };
+/// assert_eq!(message, "Please give a name");
+/// ```
+///
+/// ```compile_fail
+/// use weechat_command_parser::Error;
+///
+/// fn describe(error: &Error) -> &'static str {
+///     match error {
+///         Error::RequiredArgMissing(_) => "missing arg",
+///         Error::FlagValueMissing(..) => "missing value",
+///         Error::UnbalancedQuote => "unbalanced quote",
+///         Error::TooManyArgs { .. } => "too many args",
+///         Error::TooFewArgs { .. } => "too few args",
+///         Error::InvalidFlagValue { .. } => "invalid flag value",
+///         Error::MissingRequiredGroup(_) => "missing flag",
+///         Error::ConflictingFlags(_) => "conflicting flags",
+///         Error::UnexpectedArg { .. } => "unexpected arg",
+///         Error::UnknownFlag(..) => "unknown flag",
+///         Error::InSubcommand { .. } => "in subcommand",
+///         Error::MaxDepthExceeded => "too deep",
+///         Error::InvalidChoice { .. } => "invalid choice",
+///         Error::AmbiguousSubcommand { .. } => "ambiguous subcommand",
+///         Error::InvalidKeyValue(..) => "invalid pair",
+///         Error::UnexpectedFlag(..) => "unexpected flag",
+///         Error::InvalidArgValue { .. } => "invalid arg value",
+///         Error::ValidationFailed(_) => "validation failed",
+///     }
+/// }
+/// ```
+///
+/// Errors about a specific token also hold its byte range in the parsed input.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub enum Error {
     RequiredArgMissing(String),
+    FlagValueMissing(String, Range<usize>),
+    UnbalancedQuote,
+    TooManyArgs {
+        max: usize,
+        got: usize,
+    },
+    /// None of the flags in a [`Command::require_one_of`] group were given.
+    MissingRequiredGroup(Vec<String>),
+    /// More than one flag of a [`Command::exclusive_flags`] group was given.
+    ConflictingFlags(Vec<String>),
+    /// A positional arg beyond the declared args, see [`Command::deny_extra_args`]. The position
+    /// is zero based and counts shared args.
+    UnexpectedArg {
+        value: String,
+        position: usize,
+        span: Range<usize>,
+    },
+    /// A token that looks like a flag but isn't registered, see [`Policy::Error`].
+    UnknownFlag(String, Range<usize>),
+    /// An error while parsing a subcommand, `path` holds the subcommand names from the outermost
+    /// one down to the one that failed.
+    InSubcommand {
+        path: Vec<String>,
+        source: Box<Error>,
+    },
+    /// More levels of subcommands than [`Command::max_subcommand_depth`] allows.
+    MaxDepthExceeded,
+    /// An arg value that isn't one of its [`Command::arg_choices`].
+    InvalidChoice {
+        arg: String,
+        value: String,
+        choices: Vec<String>,
+        span: Range<usize>,
+    },
+    /// Fewer positional args than [`Command::arg_range`] requires.
+    TooFewArgs {
+        min: usize,
+        got: usize,
+    },
+    /// The value of a [`Command::int_value_flag`] isn't a number in range.
+    /// The span covers the flag and its value.
+    InvalidFlagValue {
+        flag: String,
+        message: String,
+        span: Range<usize>,
+    },
+    /// An abbreviation matching more than one subcommand, see
+    /// [`Command::allow_subcommand_abbreviation`].
+    AmbiguousSubcommand {
+        input: String,
+        candidates: Vec<String>,
+        span: Range<usize>,
+    },
+    /// A token of a [`Command::kv_arg`] without a `key=` part.
+    InvalidKeyValue(String, Range<usize>),
+    /// A token that looks like a flag given to a [`Command::no_flags`] command.
+    UnexpectedFlag(String, Range<usize>),
+    /// An arg that doesn't parse as the type asked for by [`Command::parse_typed`], or doesn't
+    /// match the pattern of a `Command::arg_pattern` arg. The span is `None` for a default.
+    InvalidArgValue {
+        arg: String,
+        value: String,
+        message: String,
+        span: Option<Range<usize>>,
+    },
+    /// A [`Command::validate_with`] check rejected the parsed command.
+    ValidationFailed(String),
 }
 
 impl fmt::Display for Error {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
         match self {
             Error::RequiredArgMissing(arg) => write!(f, "Missing required arg \"{}\"", arg),
+            Error::FlagValueMissing(flag, _) => write!(f, "Missing value for flag \"{}\"", flag),
+            Error::UnbalancedQuote => write!(f, "Unbalanced quote"),
+            Error::InvalidFlagValue { flag, message, .. } => {
+                write!(f, "Value of flag \"{}\" {}", flag, message)
+            }
+            Error::TooFewArgs { min, got } => {
+                write!(f, "Expected at least {} args, got {}", min, got)
+            }
+            Error::TooManyArgs { max, got } => {
+                write!(f, "Expected at most {} args, got {}", max, got)
+            }
+            Error::MissingRequiredGroup(flags) => {
+                write!(f, "Expected one of {}", flags.join(", "))
+            }
+            Error::ConflictingFlags(flags) => {
+                write!(f, "Flags {} can't be used together", flags.join(", "))
+            }
+            Error::UnexpectedArg {
+                value, position, ..
+            } => {
+                write!(f, "Unexpected arg \"{}\" at position {}", value, position)
+            }
+            Error::UnknownFlag(flag, _) => write!(f, "Unknown flag \"{}\"", flag),
+            Error::InvalidArgValue {
+                arg,
+                value,
+                message,
+                ..
+            } => write!(
+                f,
+                "Invalid value \"{}\" for \"{}\": {}",
+                value, arg, message
+            ),
+            Error::UnexpectedFlag(flag, _) => write!(f, "Unexpected flag \"{}\"", flag),
+            Error::ValidationFailed(message) => write!(f, "{}", message),
+            Error::InvalidKeyValue(value, _) => write!(f, "Expected key=value, got \"{}\"", value),
+            Error::MaxDepthExceeded => write!(f, "Too many levels of subcommands"),
+            Error::InvalidChoice {
+                arg,
+                value,
+                choices,
+                ..
+            } => write!(
+                f,
+                "Invalid value \"{}\" for \"{}\", expected one of {}",
+                value,
+                arg,
+                choices.join(", ")
+            ),
+            Error::AmbiguousSubcommand {
+                input, candidates, ..
+            } => write!(
+                f,
+                "Ambiguous subcommand \"{}\", could be {}",
+                input,
+                candidates.join(", ")
+            ),
+            Error::InSubcommand { path, source } => write!(f, "{} (in {})", source, path.join(" ")),
+        }
+    }
+}
+
+impl Error {
+    /// Wrap the error as happening in the subcommand `name`.
+    fn in_subcommand(self, name: &str) -> Self {
+        match self {
+            Error::InSubcommand { mut path, source } => {
+                path.insert(0, name.to_owned());
+                Error::InSubcommand { path, source }
+            }
+            other => Error::InSubcommand {
+                path: vec![name.to_owned()],
+                source: Box::new(other),
+            },
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::InSubcommand { source, .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+/// Renders an [`Error`] as a user facing message.
+///
+/// The [`Display`](fmt::Display) impl of [`Error`] produces English messages, implement this to
+/// provide translated or otherwise customized ones. Closures taking an `&Error` implement it too.
+pub trait ErrorFormatter {
+    fn format(&self, error: &Error) -> String;
+}
+
+impl<F> ErrorFormatter for F
+where
+    F: Fn(&Error) -> String,
+{
+    fn format(&self, error: &Error) -> String {
+        self(error)
+    }
+}
+
+impl Error {
+    /// Render this error using a custom formatter instead of the default messages.
+    pub fn format_with<F: ErrorFormatter + ?Sized>(&self, formatter: &F) -> String {
+        formatter.format(self)
+    }
+
+    /// Render the message followed by `input` and a caret line pointing at the problem.
+    ///
+    /// ```text
+    /// Unknown flag "-x"
+    /// /say -x hi
+    ///      ^^
+    /// ```
+    ///
+    /// Offending tokens are marked with the span recorded while parsing, so `input` has to be
+    /// what was parsed. Missing args point right after it. Errors that can't be located, like
+    /// [`Error::ConflictingFlags`], only give the message.
+    pub fn render_with_source(&self, input: &str) -> String {
+        let message = self.to_string();
+        let span = match self.source_span(input) {
+            Some(span) => span,
+            None => return message,
+        };
+
+        let column = input[..span.start].chars().count();
+        let width = input[span].chars().count().max(1);
+
+        let mut rendered = String::with_capacity(message.len() + input.len() + column + width + 2);
+        rendered.push_str(&message);
+        rendered.push('\n');
+        rendered.push_str(input);
+        rendered.push('\n');
+        rendered.extend(std::iter::repeat_n(' ', column));
+        rendered.extend(std::iter::repeat_n('^', width));
+        rendered
+    }
+
+    /// Where in `input` the error happened, if it can be told.
+    fn source_span(&self, input: &str) -> Option<Range<usize>> {
+        let span = match self {
+            Error::UnbalancedQuote => {
+                return tokenizer::unclosed_quote(input).map(|start| start..input.len());
+            }
+            Error::RequiredArgMissing(_)
+            | Error::MissingRequiredGroup(_)
+            | Error::TooFewArgs { .. } => {
+                let end = input.trim_end_matches(' ').len();
+                return Some(end..end);
+            }
+            Error::FlagValueMissing(_, span)
+            | Error::UnknownFlag(_, span)
+            | Error::UnexpectedFlag(_, span)
+            | Error::InvalidKeyValue(_, span)
+            | Error::InvalidFlagValue { span, .. }
+            | Error::UnexpectedArg { span, .. }
+            | Error::InvalidChoice { span, .. }
+            | Error::AmbiguousSubcommand { span, .. }
+            | Error::InvalidArgValue {
+                span: Some(span), ..
+            } => span,
+            Error::InSubcommand { source, .. } => return source.source_span(input),
+            _ => return None,
+        };
+
+        input.get(span.clone())?;
+        Some(span.clone())
+    }
+}
+
+/// A mistake in the definition of a [`Command`], found by [`Command::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    UnregisteredGroupFlag {
+        group: String,
+        flag: String,
+    },
+    RestArgNotLast(String),
+    InvalidSpec(String),
+    UnregisteredFlag(String),
+    /// A registered flag doesn't start with any of the [`Command::flag_prefixes`].
+    FlagMissingPrefix(String),
+    /// A command or subcommand was never given a name.
+    EmptyName,
+    /// Two args of the same command, including shared args, have the same name.
+    DuplicateArg(String),
+    /// Two subcommands of the same command have the same name.
+    DuplicateSubcommand(String),
+    /// A subcommand name or alias starts with one of the [`Command::flag_prefixes`] of its
+    /// parent, or with `-` if none are set.
+    SubcommandLooksLikeFlag(String),
+    /// A flag was added to more than one [`Command::exclusive_flags`] group.
+    FlagInMultipleGroups(String),
+    /// A [`Command::single_rest_arg`] is not the only arg of its command.
+    SingleRestArgNotAlone(String),
+    /// The pattern of a `Command::arg_pattern` arg is not a valid regex.
+    InvalidPattern {
+        arg: String,
+        message: String,
+    },
+    /// The named command has both args and subcommands without [`Command::allow_mixed`] or
+    /// [`Command::root_args_when_no_subcommand`].
+    SubcommandAndArgsConflict(String),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::UnregisteredGroupFlag { group, flag } => write!(
+                f,
+                "Flag \"{}\" in group \"{}\" is not registered",
+                flag, group
+            ),
+            BuildError::RestArgNotLast(arg) => {
+                write!(f, "Rest arg \"{}\" must be the last arg", arg)
+            }
+            BuildError::InvalidSpec(token) => write!(f, "Invalid command spec \"{}\"", token),
+            BuildError::UnregisteredFlag(flag) => write!(f, "Flag \"{}\" is not registered", flag),
+            BuildError::FlagMissingPrefix(flag) => {
+                write!(f, "Flag \"{}\" is missing a prefix", flag)
+            }
+            BuildError::EmptyName => write!(f, "Command name is empty"),
+            BuildError::DuplicateArg(arg) => write!(f, "Arg \"{}\" is declared twice", arg),
+            BuildError::DuplicateSubcommand(name) => {
+                write!(f, "Subcommand \"{}\" is declared twice", name)
+            }
+            BuildError::FlagInMultipleGroups(flag) => {
+                write!(f, "Flag \"{}\" is in more than one exclusive group", flag)
+            }
+            BuildError::SubcommandLooksLikeFlag(name) => {
+                write!(f, "Subcommand \"{}\" looks like a flag", name)
+            }
+            BuildError::SingleRestArgNotAlone(arg) => {
+                write!(f, "Arg \"{}\" must be the only arg", arg)
+            }
+            BuildError::InvalidPattern { arg, message } => {
+                write!(f, "Invalid pattern for arg \"{}\": {}", arg, message)
+            }
+            BuildError::SubcommandAndArgsConflict(name) => {
+                write!(f, "Command \"{}\" has both args and subcommands", name)
+            }
         }
     }
 }
 
-impl error::Error for Error {}
+impl error::Error for BuildError {}
+
+/// A potential ambiguity in a [`Command`], reported by [`Command::check_ambiguities`].
+///
+/// `command` is the name of the command the problem was found in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ambiguity {
+    /// Two subcommands share the same name or alias.
+    DuplicateSubcommand { command: String, name: String },
+    /// A subcommand name or alias is a prefix of one of another subcommand, so abbreviations of
+    /// `name` may match `prefix`.
+    SubcommandPrefix {
+        command: String,
+        prefix: String,
+        name: String,
+    },
+    /// A flag is a prefix of another flag, so abbreviations of `flag` may match `prefix`.
+    FlagPrefix {
+        command: String,
+        prefix: String,
+        flag: String,
+    },
+}
+
+/// What to do with a token in flag position that starts with `-` but isn't a registered flag,
+/// set with [`Command::unknown_flags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Policy {
+    /// Stop parsing flags and keep the token, dash included, as the first arg. This is the
+    /// default.
+    AsArg,
+    /// Fail with [`Error::UnknownFlag`].
+    Error,
+}
+
+/// Whether flags or args come first when a flag and an arg share a name, set with
+/// [`Command::lookup_priority`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Priority {
+    /// The flag wins. This is the default.
+    FlagsFirst,
+    /// The arg wins.
+    ArgsFirst,
+}
 
 #[derive(Clone)]
 struct ArgRule {
     pub name: String,
     pub required: bool,
+    pub rest: bool,
+    pub strip_prefix: Option<String>,
+    pub default: Option<String>,
+    pub hint: Option<String>,
+    pub choices: Vec<String>,
+    pub until_flag: bool,
+    /// Flags that make a required arg optional.
+    pub required_unless: Vec<String>,
+    /// A rest arg whose tokens are also split into `key=value` pairs.
+    pub pairs: bool,
+    /// Declared with [`Command::single_rest_arg`], so it must be the only arg.
+    pub single: bool,
+    #[cfg(feature = "regex")]
+    pub pattern: Option<ArgPattern>,
+}
+
+/// The pattern of a [`Command::arg_pattern`], compiled once when it is added.
+#[cfg(feature = "regex")]
+#[derive(Clone)]
+struct ArgPattern {
+    source: String,
+    /// The message of the compile error for an invalid pattern, see
+    /// [`BuildError::InvalidPattern`].
+    regex: Result<regex::Regex, String>,
+}
+
+impl ArgRule {
+    fn new(name: &str, required: bool) -> Self {
+        Self {
+            name: name.to_owned(),
+            required,
+            rest: false,
+            strip_prefix: None,
+            default: None,
+            hint: None,
+            choices: Vec::new(),
+            until_flag: false,
+            required_unless: Vec::new(),
+            pairs: false,
+            single: false,
+            #[cfg(feature = "regex")]
+            pattern: None,
+        }
+    }
+
+    /// Apply the normalization configured for this arg to a parsed value.
+    fn normalize(&self, value: String) -> String {
+        match self
+            .strip_prefix
+            .as_deref()
+            .and_then(|prefix| value.strip_prefix(prefix))
+        {
+            Some(stripped) => stripped.to_owned(),
+            None => value,
+        }
+    }
+
+    /// Whether the arg has to be given when these flags are set.
+    fn is_required(&self, flags: &HashSet<String>) -> bool {
+        self.required && !self.required_unless.iter().any(|flag| flags.contains(flag))
+    }
+
+    /// Normalize a parsed value and check it against the choices.
+    fn accept(
+        &self,
+        value: String,
+        span: &Range<usize>,
+        context: &mut Context,
+    ) -> Result<String, Error> {
+        let value = self.normalize(value);
+
+        if !self.choices.is_empty() && !self.choices.contains(&value) {
+            context.report(Error::InvalidChoice {
+                arg: self.name.clone(),
+                value: value.clone(),
+                choices: self.choices.clone(),
+                span: span.clone(),
+            })?;
+        }
+
+        #[cfg(feature = "regex")]
+        if let Some(ArgPattern {
+            source,
+            regex: Ok(regex),
+        }) = &self.pattern
+        {
+            if !regex.is_match(&value) {
+                context.report(Error::InvalidArgValue {
+                    arg: self.name.clone(),
+                    value: value.clone(),
+                    message: format!("must match {}", source),
+                    span: Some(span.clone()),
+                })?;
+            }
+        }
+        Ok(value)
+    }
+
+    /// The arg to use when no value was given, defaulted args have an empty span.
+    fn default_arg(&self) -> Option<ParsedArg> {
+        self.default.as_ref().map(|value| ParsedArg {
+            name: self.name.clone(),
+            value: value.clone(),
+            inherited: false,
+            defaulted: true,
+            span: 0..0,
+        })
+    }
 }
 
 #[derive(Clone)]
 pub struct Command {
     name: String,
+    aliases: Vec<String>,
+    description: Option<String>,
     flags: HashSet<String>,
+    value_flags: HashSet<String>,
+    rest_flags: HashSet<String>,
+    multi_flags: HashSet<String>,
     args: Vec<ArgRule>,
     subcommands: Vec<Command>,
+    flag_groups: Vec<(String, Vec<String>)>,
+    required_groups: Vec<Vec<String>>,
+    exclusive_groups: Vec<Vec<String>>,
+    shared_args: Vec<ArgRule>,
+    flag_detector: Option<fn(&str) -> bool>,
+    opaque: bool,
+    allow_quotes: bool,
+    min_args: usize,
+    max_args: Option<usize>,
+    range_max: Option<usize>,
+    deny_extra_args: bool,
+    unknown_flags: Policy,
+    flatten: bool,
+    flag_prefixes: Vec<String>,
+    max_depth: usize,
+    default_on: Vec<String>,
+    int_flags: Vec<(String, RangeInclusive<i64>)>,
+    abbreviate_subcommands: bool,
+    pre_tokenize: Option<fn(Vec<String>) -> Vec<String>>,
+    validators: Vec<Validator>,
+    args_without_subcommand: bool,
+    no_flags: bool,
+    trim_args: bool,
+    ignore_flag_case: bool,
+    allow_mixed: bool,
+    exact_name: bool,
+    priority: Priority,
+}
+
+impl Default for Command {
+    /// A command with an empty name, see [`Command::set_name`].
+    fn default() -> Self {
+        Self::new("")
+    }
 }
 
 impl Command {
+    /// Create a command, `name` may contain spaces to match several leading tokens.
+    ///
+    /// The name is matched exactly, so an escaped `//name` is not taken as `/name` and ends up as
+    /// an arg instead, see [`Command::exact_name_match`].
     pub fn new(name: &str) -> Self {
         Self {
             name: name.to_owned(),
+            aliases: Vec::new(),
+            description: None,
             flags: HashSet::new(),
+            value_flags: HashSet::new(),
+            rest_flags: HashSet::new(),
+            multi_flags: HashSet::new(),
             args: Vec::new(),
             subcommands: Vec::new(),
+            flag_groups: Vec::new(),
+            required_groups: Vec::new(),
+            exclusive_groups: Vec::new(),
+            shared_args: Vec::new(),
+            flag_detector: None,
+            opaque: false,
+            allow_quotes: false,
+            min_args: 0,
+            max_args: None,
+            range_max: None,
+            deny_extra_args: false,
+            unknown_flags: Policy::AsArg,
+            flatten: false,
+            flag_prefixes: Vec::new(),
+            max_depth: 64,
+            default_on: Vec::new(),
+            int_flags: Vec::new(),
+            abbreviate_subcommands: false,
+            pre_tokenize: None,
+            validators: Vec::new(),
+            args_without_subcommand: false,
+            no_flags: false,
+            trim_args: false,
+            ignore_flag_case: false,
+            allow_mixed: false,
+            exact_name: true,
+            priority: Priority::FlagsFirst,
         }
     }
 
+    /// Replace the name given to [`Command::new`], for commands built in stages from
+    /// [`Command::default`].
+    ///
+    /// Parsing matches against the name set last. While the name is empty no leading token is
+    /// taken as the name, and [`Command::validate`] fails with [`BuildError::EmptyName`].
+    pub fn set_name(mut self, name: &str) -> Self {
+        self.name = name.to_owned();
+
+        self
+    }
+
+    /// A copy of this command, subcommands included, under another name.
+    ///
+    /// This builds several commands from one definition, like `/ban` and `/quiet`. Aliases are
+    /// copied as well.
+    pub fn renamed(&self, name: &str) -> Command {
+        self.clone().set_name(name)
+    }
+
+    pub fn get_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Add another name the command can be invoked with, like `/query` for `/msg`.
+    ///
+    /// [`ParsedCommand::command`] always reports the name given to [`Command::new`].
+    pub fn alias(mut self, name: &str) -> Self {
+        self.aliases.push(name.to_owned());
+
+        self
+    }
+
+    /// A one line description, shown in help output.
+    pub fn description(mut self, text: &str) -> Self {
+        self.description = Some(text.to_owned());
+
+        self
+    }
+
+    pub fn get_description(&self) -> Option<&str> {
+        self.description.as_deref()
+    }
+
+    /// Add a flag that is on unless disabled with its `no-` form, so `--color` can be turned off
+    /// with `--no-color` and `-color` with `-no-color`.
+    ///
+    /// Read it with [`ParsedCommand::bool_flag`]. [`ParsedCommand::has_flag`] still only reports
+    /// whether `--color` or `--no-color` was given.
+    pub fn default_on_flag(mut self, flag: &str) -> Self {
+        self.flags.insert(negated_flag(flag));
+        self.default_on.push(flag.to_owned());
+
+        self.flag(flag)
+    }
+
     pub fn flag(mut self, flag: &str) -> Self {
         self.flags.insert(flag.into());
 
@@ -53,264 +713,5153 @@ impl Command {
         self
     }
 
+    /// Add a flag that takes the following token as its value.
+    ///
+    /// A value flag may be repeated, in which case every value is kept in order of appearance.
+    /// A registered flag right after it is not taken as its value, so the value is missing.
+    pub fn value_flag(mut self, flag: &str) -> Self {
+        self.value_flags.insert(flag.into());
+
+        self
+    }
+
+    /// Add a value flag holding a comma separated list, read with [`ParsedCommand::flag_csv`].
+    pub fn csv_value_flag(self, flag: &str) -> Self {
+        self.value_flag(flag)
+    }
+
+    /// Add a value flag taking an integer within `range`, checked while parsing.
+    ///
+    /// Other values fail with [`Error::InvalidFlagValue`], read the number with
+    /// [`ParsedCommand::flag_as`].
+    pub fn int_value_flag(mut self, flag: &str, range: RangeInclusive<i64>) -> Self {
+        self.int_flags.push((flag.to_owned(), range));
+
+        self.value_flag(flag)
+    }
+
+    /// Add a value flag that takes every following token up to the next registered flag.
+    ///
+    /// Each token is kept as its own value, read them with [`ParsedCommand::flag_values`]. A
+    /// `--` also ends the values, so in `--to a b -- c` the flag gets `a` and `b` and the rest
+    /// are args.
+    pub fn multi_value_flag(mut self, flag: &str) -> Self {
+        self.multi_flags.insert(flag.into());
+
+        self.value_flag(flag)
+    }
+
+    /// Add a flag that takes everything after it as its value.
+    ///
+    /// Once the flag is seen flag parsing stops, and the remaining tokens are joined with single
+    /// spaces into its value, so `/exec --cmd ls -la /tmp` gives `--cmd` the value `ls -la /tmp`.
+    /// A `--` after the flag is kept in the value, while a `--` before it ends flag parsing so the
+    /// flag is read as an arg.
+    pub fn rest_after_flag(mut self, flag: &str) -> Self {
+        self.rest_flags.insert(flag.into());
+
+        self
+    }
+
+    /// Show the given flags under their own heading in [`help`].
+    ///
+    /// This only affects help output, the flags still have to be registered as usual. Flags that
+    /// are not in any group are listed under "Flags".
+    ///
+    /// [`help`]: Command::help
+    pub fn flag_group(mut self, group: &str, flags: &[&str]) -> Self {
+        let flags = flags.iter().map(|&flag| flag.to_owned());
+
+        match self.flag_groups.iter_mut().find(|(name, _)| name == group) {
+            Some((_, existing)) => existing.extend(flags),
+            None => self.flag_groups.push((group.to_owned(), flags.collect())),
+        }
+
+        self
+    }
+
+    /// Require at least one of the given flags to be present.
+    ///
+    /// Combined with [`exclusive_flags`] on the same flags this requires exactly one of them.
+    ///
+    /// [`exclusive_flags`]: Command::exclusive_flags
+    pub fn require_one_of(mut self, flags: &[&str]) -> Self {
+        self.required_groups
+            .push(flags.iter().map(|&flag| flag.to_owned()).collect());
+
+        self
+    }
+
+    /// Allow at most one of the given flags to be present.
+    ///
+    /// A flag can be in only one such group, [`Command::validate`] fails with
+    /// [`BuildError::FlagInMultipleGroups`] otherwise.
+    pub fn exclusive_flags(mut self, flags: &[&str]) -> Self {
+        self.exclusive_groups
+            .push(flags.iter().map(|&flag| flag.to_owned()).collect());
+
+        self
+    }
+
     pub fn arg(mut self, name: &str, required: bool) -> Self {
+        self.args.push(ArgRule::new(name, required));
+
+        self
+    }
+
+    /// Add an arg whose whole value must match the regex `pattern`, like `\d\d:\d\d` for a time.
+    ///
+    /// Other values fail with [`Error::InvalidArgValue`]. The pattern is compiled once here, an
+    /// invalid one is reported by [`Command::validate`] as [`BuildError::InvalidPattern`] and
+    /// accepts any value. Needs the `regex` feature.
+    #[cfg(feature = "regex")]
+    pub fn arg_pattern(mut self, name: &str, required: bool, pattern: &str) -> Self {
+        let regex =
+            regex::Regex::new(&format!("^(?:{})$", pattern)).map_err(|error| error.to_string());
         self.args.push(ArgRule {
-            name: name.to_owned(),
-            required,
+            pattern: Some(ArgPattern {
+                source: pattern.to_owned(),
+                regex,
+            }),
+            ..ArgRule::new(name, required)
         });
 
         self
     }
 
-    pub fn subcommand(mut self, subcommand: Command) -> Self {
-        self.subcommands.push(subcommand);
+    /// Add an arg that captures every remaining token, joined with single spaces.
+    ///
+    /// This must be the last arg of the command.
+    pub fn rest_arg(mut self, name: &str, required: bool) -> Self {
+        self.args.push(ArgRule {
+            rest: true,
+            ..ArgRule::new(name, required)
+        });
 
         self
     }
 
-    pub fn parse_from<I, T>(&self, iter: I) -> Result<ParsedCommand, Error>
-    where
-        I: Iterator<Item = T>,
-        T: Into<String>,
-    {
-        let mut args: Vec<String> = iter.map(Into::into).filter(|a| !a.is_empty()).collect();
-        let mut flags = HashSet::new();
+    /// Make `name` the only arg, capturing everything after the command name and leading flags.
+    ///
+    /// This is the shape of commands like `/me waves hello`. The arg is optional, use
+    /// [`Command::rest_arg`] for a required one or [`ParsedInput::arg_raw`] to keep the original
+    /// spacing. Other args, shared ones included, are reported by [`Command::validate`] as
+    /// [`BuildError::SingleRestArgNotAlone`].
+    pub fn single_rest_arg(mut self, name: &str) -> Self {
+        self.args.push(ArgRule {
+            rest: true,
+            single: true,
+            ..ArgRule::new(name, false)
+        });
+
+        self
+    }
+
+    /// Add a rest arg of `key=value` tokens, for commands like `/set a=1 b=2`.
+    ///
+    /// The pairs can be read with [`ParsedCommand::kv_args`], the whole text with
+    /// [`ParsedCommand::arg`]. A token without `=`, or with nothing before it, fails with
+    /// [`Error::InvalidKeyValue`]. Values may be empty and contain more `=`.
+    pub fn kv_arg(mut self, name: &str, required: bool) -> Self {
+        self.args.push(ArgRule {
+            rest: true,
+            pairs: true,
+            ..ArgRule::new(name, required)
+        });
+
+        self
+    }
+
+    /// Add an arg that captures the following tokens up to the next flag, joined with single
+    /// spaces.
+    ///
+    /// Flag parsing resumes at that flag, so `/topic the new topic -quiet` gives the topic
+    /// `the new topic` and sets `-quiet`, after which the remaining args are parsed as usual.
+    /// After a `--` it captures every remaining token like [`Command::rest_arg`].
+    pub fn arg_until_flag(mut self, name: &str, required: bool) -> Self {
+        self.args.push(ArgRule {
+            until_flag: true,
+            ..ArgRule::new(name, required)
+        });
 
-        let mut subcommand_match = Box::new(None);
+        self
+    }
+
+    /// Add an arg that is shared with the subcommands.
+    ///
+    /// Shared args are parsed before the subcommand token, in the order they were added, so a
+    /// shared `server` arg reads `/discord libera join #rust` as server `libera` followed by the
+    /// `join` subcommand. The value can be read from this command as well as from the matched
+    /// subcommand. An optional shared arg is skipped if the token is a subcommand name.
+    pub fn shared_arg(mut self, name: &str, required: bool) -> Self {
+        self.shared_args.push(ArgRule::new(name, required));
+
+        self
+    }
 
-        if args.get(0) == Some(&self.name) {
-            args.remove(0);
+    /// Remove a single leading `prefix` from the value of an already added arg.
+    ///
+    /// With `arg_strip_prefix("channel", "#")` both `/join #rust` and `/join rust` give `rust`,
+    /// while `##rust` gives `#rust`.
+    pub fn arg_strip_prefix(mut self, name: &str, prefix: &str) -> Self {
+        for rule in self
+            .args
+            .iter_mut()
+            .chain(&mut self.shared_args)
+            .filter(|rule| rule.name == name)
+        {
+            rule.strip_prefix = Some(prefix.to_owned());
         }
 
-        for subcommand in &self.subcommands {
-            if args.get(0) == Some(&subcommand.name) {
-                *subcommand_match = Some((
-                    subcommand.name.clone(),
-                    subcommand.parse_from(args[1..].iter())?,
-                ));
-            }
+        self
+    }
+
+    /// Use `value` for an already added optional arg when it is not given.
+    pub fn arg_default(mut self, name: &str, value: &str) -> Self {
+        for rule in self
+            .args
+            .iter_mut()
+            .chain(&mut self.shared_args)
+            .filter(|rule| rule.name == name)
+        {
+            rule.default = Some(value.to_owned());
         }
 
-        for arg in args
-            .clone()
-            .into_iter()
-            .take_while(|arg| self.flags.contains(arg))
+        self
+    }
+
+    /// Only accept one of `choices` for an already added arg, checked after
+    /// [`Command::arg_strip_prefix`] is applied. Defaults are not checked.
+    pub fn arg_choices(mut self, name: &str, choices: &[&str]) -> Self {
+        for rule in self
+            .args
+            .iter_mut()
+            .chain(&mut self.shared_args)
+            .filter(|rule| rule.name == name)
         {
-            flags.insert(arg);
-            args.remove(0);
+            rule.choices = choices.iter().map(|&choice| choice.to_owned()).collect();
         }
 
-        let mut parsed_args = Vec::new();
+        self
+    }
 
-        for (rule, arg) in self.args.iter().zip(
-            args.into_iter()
-                .map(Option::Some)
-                .chain(std::iter::repeat(None)),
-        ) {
-            match arg {
-                Some(arg) => parsed_args.push(ParsedArg {
-                    name: rule.name.clone(),
-                    value: arg,
-                }),
-                None => {
-                    if rule.required {
-                        return Err(Error::RequiredArgMissing(rule.name.clone()));
-                    }
-                }
-            };
+    /// Make an already added arg required unless `flag` is given, like a reason unless `-silent`.
+    ///
+    /// Calling this again with other flags lets any of them make the arg optional.
+    pub fn arg_required_unless(mut self, name: &str, flag: &str) -> Self {
+        for rule in self
+            .args
+            .iter_mut()
+            .chain(&mut self.shared_args)
+            .filter(|rule| rule.name == name)
+        {
+            rule.required = true;
+            rule.required_unless.push(flag.to_owned());
         }
 
-        Ok(ParsedCommand {
-            command: self.name.clone(),
-            flags,
-            args: parsed_args,
-            subcommand_match,
-        })
+        self
     }
 
-    pub fn parse(&self, input: &str) -> Result<ParsedCommand, Error> {
-        self.parse_from(input.split(' '))
+    /// Attach a free form completion hint like `nick` or `channel` to an already added arg.
+    ///
+    /// The parser doesn't interpret it, [`Command::complete`] hands it back to the caller.
+    pub fn arg_hint(mut self, name: &str, hint: &str) -> Self {
+        for rule in self
+            .args
+            .iter_mut()
+            .chain(&mut self.shared_args)
+            .filter(|rule| rule.name == name)
+        {
+            rule.hint = Some(hint.to_owned());
+        }
+
+        self
     }
-}
 
-#[derive(Debug, Clone)]
-struct ParsedArg {
-    pub name: String,
-    pub value: String,
-}
+    /// Remove every flag, including value flags and their ranges, registered so far.
+    ///
+    /// The default on flags and the flag groups, required groups and exclusive groups are
+    /// removed too, since they name the removed flags.
+    pub fn clear_flags(mut self) -> Self {
+        self.flags.clear();
+        self.value_flags.clear();
+        self.rest_flags.clear();
+        self.multi_flags.clear();
+        self.int_flags.clear();
+        self.default_on.clear();
+        self.flag_groups.clear();
+        self.required_groups.clear();
+        self.exclusive_groups.clear();
 
-#[derive(Debug, Clone)]
-pub struct ParsedCommand {
-    command: String,
-    flags: HashSet<String>,
-    args: Vec<ParsedArg>,
-    subcommand_match: Box<Option<(String, ParsedCommand)>>,
-}
+        self
+    }
 
-impl ParsedCommand {
-    pub fn has_flag(&self, flag: &str) -> bool {
-        self.flags.contains(flag)
+    /// Remove every arg, including shared args, registered so far.
+    pub fn clear_args(mut self) -> Self {
+        self.args.clear();
+        self.shared_args.clear();
+
+        self
     }
 
-    pub fn arg(&self, name: &str) -> Option<&str> {
-        self.args
-            .iter()
-            .find(|arg| arg.name == name)
-            .map(|arg| arg.value.as_ref())
+    pub fn subcommand(mut self, subcommand: Command) -> Self {
+        self.subcommands.push(subcommand);
+
+        self
     }
 
-    pub fn args(&self) -> Vec<&str> {
-        self.args.iter().map(|arg| arg.value.as_ref()).collect()
+    /// Find an already added subcommand by name or alias, to change it in place.
+    ///
+    /// The builder methods take the command by value, so use them through [`std::mem::take`]:
+    /// `*sub = std::mem::take(sub).flag("-all")`.
+    pub fn subcommand_mut(&mut self, name: &str) -> Option<&mut Command> {
+        self.subcommands.iter_mut().find(|subcommand| {
+            subcommand.name == name || subcommand.aliases.iter().any(|alias| alias == name)
+        })
     }
 
-    pub fn rest<'a>(&self, raw: &'a str) -> &'a str {
-        if let Some(index) = raw.find(&self.command) {
-            &raw[(index + self.command.len())..]
-        } else {
-            raw
-        }
+    /// Add a subcommand whose arguments are not parsed.
+    ///
+    /// Everything after the subcommand name is captured verbatim and made available through
+    /// [`ParsedCommand::raw_tokens`], which is useful for forwarding to an external handler.
+    pub fn opaque_subcommand(mut self, name: &str) -> Self {
+        let mut subcommand = Command::new(name);
+        subcommand.opaque = true;
+        self.subcommands.push(subcommand);
+
+        self
     }
 
-    pub fn subcommand(&self) -> Option<(&str, ParsedCommand)> {
-        (*self.subcommand_match)
-            .as_ref()
-            .map(|(name, cmd)| (name.as_ref(), cmd.clone()))
+    /// Let [`parse`] group quoted sections into a single token.
+    ///
+    /// Both `"double"` and `'single'` quotes are supported, with `\"` and `\\` escapes inside
+    /// double quotes. Outside quotes a backslash escapes a space, a quote or a backslash. This
+    /// applies to flag values as well, so `--msg "a b"`, `--msg="a b"` and `--msg=a\ b` all give
+    /// `a b`. A token that starts with a quote is always a plain value, so `"-foo"` is
+    /// never read as a flag. Only the setting of the command `parse` is called on matters.
+    ///
+    /// [`parse`]: Command::parse
+    pub fn allow_quotes(mut self, allow: bool) -> Self {
+        self.allow_quotes = allow;
+
+        self
     }
 
-    pub fn command(&self) -> &str {
-        &self.command
+    /// Reject input with more than `max` positional args.
+    ///
+    /// By default extra args are silently ignored. The cap is not applied when a subcommand
+    /// matched, since the remaining tokens belong to the subcommand, or when there is a rest
+    /// arg to take them.
+    pub fn max_args(mut self, max: usize) -> Self {
+        self.max_args = Some(max);
+
+        self
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use crate::Command;
+    /// Accept between `min` and `max` positional args, `None` leaving it unbounded.
+    ///
+    /// Tokens are counted after any flags, independent of the declared args, so
+    /// `/mode <target> <modes> [params...]` can use `arg_range(2, Some(5))` to allow three
+    /// params. Too few fail with [`Error::TooFewArgs`] and too many with
+    /// [`Error::TooManyArgs`], even with a rest arg. Neither check applies when a subcommand
+    /// matched, and a [`Command::max_args`] cap is kept next to the range.
+    pub fn arg_range(mut self, min: usize, max: Option<usize>) -> Self {
+        self.min_args = min;
+        self.range_max = max;
 
-    #[test]
+        self
+    }
+
+    /// Decide which tokens are flags with a custom function.
+    ///
+    /// By default only registered flags are recognized. With a detector, every token it accepts
+    /// is a flag, registered or not, which allows schemes like IRC style `+mode`/`-mode` flags.
+    /// Tokens it rejects are never flags, even if registered. Registered value flags still take
+    /// a value. Flag parsing always stops at a bare `--`, which is consumed before the detector
+    /// sees it.
+    pub fn flag_detector(mut self, detector: fn(&str) -> bool) -> Self {
+        self.flag_detector = Some(detector);
+
+        self
+    }
+
+    /// Transform the tokens before parsing, to expand aliases or macros.
+    ///
+    /// The hook runs after the input is split into tokens but before the command name,
+    /// subcommands and flags are matched. When it changes the tokens, spans refer to the new
+    /// tokens joined by single spaces and quoted tokens are no longer kept from being flags. Only
+    /// the hook of the command `parse` is called on runs.
+    pub fn pre_tokenize(mut self, hook: fn(Vec<String>) -> Vec<String>) -> Self {
+        self.pre_tokenize = Some(hook);
+
+        self
+    }
+
+    /// Check the parsed command once everything else passed, for rules like "`-o` needs a
+    /// file".
+    ///
+    /// An `Err` fails the parse with [`Error::ValidationFailed`]. Checks run in the order they
+    /// were added, and those of a subcommand only see the subcommand.
+    pub fn validate_with(mut self, check: fn(&ParsedCommand) -> Result<(), String>) -> Self {
+        self.validators.push(check);
+
+        self
+    }
+
+    /// Make the flags and args of an already added subcommand readable from this command.
+    ///
+    /// After `flatten_subcommand("add")`, [`ParsedCommand::has_flag`],
+    /// [`ParsedCommand::flag_value`], [`ParsedCommand::flag_values`] and [`ParsedCommand::arg`]
+    /// on the parent also look at a matched `add`. When both levels have a value for the same
+    /// name the parent's is returned. Iteration and positional access stay per level, and the
+    /// subcommand can still be read with [`ParsedCommand::subcommand`].
+    pub fn flatten_subcommand(mut self, name: &str) -> Self {
+        for subcommand in self.subcommands.iter_mut().filter(|sub| sub.name == name) {
+            subcommand.flatten = true;
+        }
+
+        self
+    }
+
+    /// Make [`Command::validate`] reject registered flags that don't start with one of
+    /// `prefixes`, catching `flag("foo")` when `flag("-foo")` was meant.
+    ///
+    /// No prefix is required by default. This only applies to this command, not its
+    /// subcommands.
+    pub fn flag_prefixes(mut self, prefixes: &[&str]) -> Self {
+        self.flag_prefixes = prefixes.iter().map(|&prefix| prefix.to_owned()).collect();
+
+        self
+    }
+
+    /// Fail with [`Error::MaxDepthExceeded`] when the input descends more than `depth` levels of
+    /// subcommands, 64 by default.
+    ///
+    /// Only the limit of the command `parse` is called on is used.
+    pub fn max_subcommand_depth(mut self, depth: usize) -> Self {
+        self.max_depth = depth;
+
+        self
+    }
+
+    /// Only parse the positional args of this command when none of its subcommands matched.
+    ///
+    /// This separates two forms of one command, like `/query help` and `/query <nick> <msg>`.
+    /// Otherwise the args are filled from the tokens the subcommand leaves unused, see
+    /// [`Command::allow_mixed`]. Shared args are not affected.
+    pub fn root_args_when_no_subcommand(mut self, only: bool) -> Self {
+        self.args_without_subcommand = only;
+
+        self
+    }
+
+    /// Allow args next to subcommands.
+    ///
+    /// When a subcommand matches, the args are filled from the tokens it leaves unused, so they
+    /// only get values if the subcommand doesn't take every token.
+    ///
+    /// Without this, or [`Command::root_args_when_no_subcommand`], declaring both fails
+    /// [`Command::validate`] with [`BuildError::SubcommandAndArgsConflict`]. Shared args are
+    /// always allowed.
+    pub fn allow_mixed(mut self, allow: bool) -> Self {
+        self.allow_mixed = allow;
+
+        self
+    }
+
+    /// Match subcommands of this command by any unique prefix of their name or aliases, so
+    /// `/discord conn` runs `connect`.
+    ///
+    /// An exact name always wins, even if it is also the prefix of another subcommand. A prefix
+    /// of more than one fails with [`Error::AmbiguousSubcommand`].
+    pub fn allow_subcommand_abbreviation(mut self, allow: bool) -> Self {
+        self.abbreviate_subcommands = allow;
+
+        self
+    }
+
+    /// Choose whether [`ParsedCommand::get`] and [`ParsedCommand::get_all`] give flags or args
+    /// precedence when they share a name, [`Priority::FlagsFirst`] by default.
+    ///
+    /// [`Command::check_ambiguities`] doesn't flag such names, this is for commands that use them
+    /// on purpose.
+    pub fn lookup_priority(mut self, priority: Priority) -> Self {
+        self.priority = priority;
+
+        self
+    }
+
+    /// Choose how unknown dash tokens before the args are handled.
+    ///
+    /// A lone `-`, tokens after `--` and tokens rejected by a [flag
+    /// detector](Command::flag_detector) are always args. A single token can be escaped as
+    /// `\-foo`, which is read as the arg `-foo`.
+    pub fn unknown_flags(mut self, policy: Policy) -> Self {
+        self.unknown_flags = policy;
+
+        self
+    }
+
+    /// Fail with [`Error::UnexpectedFlag`] for any arg that looks like a flag, for commands that
+    /// take none.
+    ///
+    /// Unlike [`Policy::Error`] this applies to every arg, not just the leading ones. Negative
+    /// numbers, a lone `-`, quoted tokens and tokens after `--` are still args.
+    pub fn no_flags(mut self, forbid: bool) -> Self {
+        self.no_flags = forbid;
+
+        self
+    }
+
+    /// Trim whitespace such as tabs from the ends of arg values.
+    ///
+    /// With [`Command::allow_quotes`] the spaces inside a quoted token are kept, so `" x "` stays
+    /// ` x `. Flag values are not trimmed.
+    pub fn trim_args(mut self, trim: bool) -> Self {
+        self.trim_args = trim;
+
+        self
+    }
+
+    /// Require the first token to equal the name or an alias exactly, the default.
+    ///
+    /// When disabled, extra leading slashes are ignored so `//say` is read as `/say`.
+    pub fn exact_name_match(mut self, exact: bool) -> Self {
+        self.exact_name = exact;
+
+        self
+    }
+
+    /// Match flags ignoring ASCII case, so `-Foo` is read as a registered `-foo`.
+    ///
+    /// Lookups such as [`ParsedCommand::has_flag`] use the registered name, while
+    /// [`ParsedCommand::flags`] reports each flag as it was typed.
+    pub fn ignore_flag_case(mut self, ignore: bool) -> Self {
+        self.ignore_flag_case = ignore;
+
+        self
+    }
+
+    /// Reject positional args beyond the declared ones instead of ignoring them.
+    ///
+    /// Commands with a rest arg never have extra args. As with [`max_args`], tokens following a
+    /// matched subcommand are left to the subcommand.
+    ///
+    /// [`max_args`]: Command::max_args
+    pub fn deny_extra_args(mut self, deny: bool) -> Self {
+        self.deny_extra_args = deny;
+
+        self
+    }
+
+    /// The name of the arg at `index`, counting shared args first and then args, in the order
+    /// they were added.
+    pub fn arg_name_at(&self, index: usize) -> Option<&str> {
+        self.shared_args
+            .iter()
+            .chain(&self.args)
+            .nth(index)
+            .map(|rule| rule.name.as_ref())
+    }
+
+    /// The position of the arg called `name`, the reverse of [`Command::arg_name_at`].
+    pub fn arg_index(&self, name: &str) -> Option<usize> {
+        self.shared_args
+            .iter()
+            .chain(&self.args)
+            .position(|rule| rule.name == name)
+    }
+
+    /// Check the definition of this command and its subcommands for mistakes.
+    ///
+    /// This returns the first of the [`problems`](Command::problems).
+    pub fn validate(&self) -> Result<(), BuildError> {
+        match self.problems().into_iter().next() {
+            Some(problem) => Err(problem),
+            None => Ok(()),
+        }
+    }
+
+    /// Collect every mistake in the definition of this command and its subcommands.
+    ///
+    /// Problems are reported depth first, in the order [`validate`](Command::validate) checks
+    /// them.
+    pub fn problems(&self) -> Vec<BuildError> {
+        let mut problems = Vec::new();
+        self.collect_problems(&mut problems);
+        problems
+    }
+
+    fn collect_problems(&self, problems: &mut Vec<BuildError>) {
+        if self.name.trim().is_empty() {
+            problems.push(BuildError::EmptyName);
+        }
+
+        for (group, flags) in &self.flag_groups {
+            for flag in flags.iter().filter(|flag| !self.is_flag(flag)) {
+                problems.push(BuildError::UnregisteredGroupFlag {
+                    group: group.clone(),
+                    flag: flag.clone(),
+                });
+            }
+        }
+
+        for flag in self
+            .required_groups
+            .iter()
+            .chain(&self.exclusive_groups)
+            .flatten()
+        {
+            if !self.is_flag(flag) {
+                problems.push(BuildError::UnregisteredFlag(flag.clone()));
+            }
+        }
+
+        let mut reported: Vec<&String> = Vec::new();
+        for (i, group) in self.exclusive_groups.iter().enumerate() {
+            for flag in group {
+                let earlier = self.exclusive_groups[..i]
+                    .iter()
+                    .any(|other| other.contains(flag));
+                if earlier && !reported.contains(&flag) {
+                    reported.push(flag);
+                    problems.push(BuildError::FlagInMultipleGroups(flag.clone()));
+                }
+            }
+        }
+
+        let rules: Vec<&ArgRule> = self.shared_args.iter().chain(&self.args).collect();
+        for (i, rule) in rules.iter().enumerate() {
+            if rules[..i].iter().any(|other| other.name == rule.name) {
+                problems.push(BuildError::DuplicateArg(rule.name.clone()));
+            }
+        }
+
+        for rule in self.args.iter().rev().skip(1).filter(|rule| rule.rest) {
+            problems.push(BuildError::RestArgNotLast(rule.name.clone()));
+        }
+
+        if rules.len() > 1 {
+            for rule in rules.iter().filter(|rule| rule.single) {
+                problems.push(BuildError::SingleRestArgNotAlone(rule.name.clone()));
+            }
+        }
+
+        #[cfg(feature = "regex")]
+        for rule in &self.args {
+            if let Some(ArgPattern {
+                regex: Err(message),
+                ..
+            }) = &rule.pattern
+            {
+                problems.push(BuildError::InvalidPattern {
+                    arg: rule.name.clone(),
+                    message: message.clone(),
+                });
+            }
+        }
+
+        let mixed = self.allow_mixed || self.args_without_subcommand;
+        if !self.args.is_empty() && !self.subcommands.is_empty() && !mixed {
+            problems.push(BuildError::SubcommandAndArgsConflict(self.name.clone()));
+        }
+
+        if !self.flag_prefixes.is_empty() {
+            let mut flags: Vec<&String> = self
+                .flags
+                .iter()
+                .chain(&self.value_flags)
+                .chain(&self.rest_flags)
+                .collect();
+            flags.sort();
+
+            for flag in flags.into_iter().filter(|flag| {
+                !self
+                    .flag_prefixes
+                    .iter()
+                    .any(|prefix| flag.starts_with(prefix.as_str()))
+            }) {
+                problems.push(BuildError::FlagMissingPrefix(flag.clone()));
+            }
+        }
+
+        for (i, subcommand) in self.subcommands.iter().enumerate() {
+            if self.subcommands[..i]
+                .iter()
+                .any(|other| other.name == subcommand.name)
+            {
+                problems.push(BuildError::DuplicateSubcommand(subcommand.name.clone()));
+            }
+
+            let looks_like_flag = |name: &String| {
+                if self.flag_prefixes.is_empty() {
+                    name.starts_with('-')
+                } else {
+                    self.flag_prefixes
+                        .iter()
+                        .any(|prefix| name.starts_with(prefix.as_str()))
+                }
+            };
+            for name in std::iter::once(&subcommand.name)
+                .chain(&subcommand.aliases)
+                .filter(|name| looks_like_flag(name))
+            {
+                problems.push(BuildError::SubcommandLooksLikeFlag(name.clone()));
+            }
+        }
+
+        for subcommand in &self.subcommands {
+            subcommand.collect_problems(problems);
+        }
+    }
+
+    /// Each subcommand name with the shortest abbreviation that selects it, for help output along
+    /// with [`Command::allow_subcommand_abbreviation`].
+    ///
+    /// Names and aliases of the other subcommands are taken into account. A name that is a
+    /// prefix of another one can only be abbreviated to itself.
+    pub fn subcommand_abbreviations(&self) -> Vec<(&str, &str)> {
+        self.subcommands
+            .iter()
+            .enumerate()
+            .map(|(i, subcommand)| {
+                let others: Vec<&str> = self
+                    .subcommands
+                    .iter()
+                    .enumerate()
+                    .filter(|&(j, _)| j != i)
+                    .flat_map(|(_, other)| std::iter::once(&other.name).chain(&other.aliases))
+                    .map(String::as_str)
+                    .collect();
+                let name = subcommand.name.as_str();
+                (name, shortest_prefix(name, &others))
+            })
+            .collect()
+    }
+
+    /// Call `visitor` with this command and every subcommand below it, along with its depth.
+    ///
+    /// Commands are visited in pre-order, a command before its subcommands and subcommands in
+    /// the order they were added. This command has depth 0.
+    pub fn walk(&self, visitor: &mut impl FnMut(&Command, usize)) {
+        self.walk_from(0, visitor);
+    }
+
+    fn walk_from(&self, depth: usize, visitor: &mut impl FnMut(&Command, usize)) {
+        visitor(self, depth);
+        for subcommand in &self.subcommands {
+            subcommand.walk_from(depth + 1, visitor);
+        }
+    }
+
+    /// Report names in this command and its subcommands that could be confused with each other.
+    ///
+    /// Unlike [`validate`] this does not mean the command is broken, it is a lint to run while
+    /// authoring commands. Problems are reported depth first, in declaration order.
+    ///
+    /// [`validate`]: Command::validate
+    pub fn check_ambiguities(&self) -> Vec<Ambiguity> {
+        let mut ambiguities = Vec::new();
+
+        for (i, a) in self.subcommands.iter().enumerate() {
+            for b in &self.subcommands[(i + 1)..] {
+                for a_name in std::iter::once(&a.name).chain(&a.aliases) {
+                    for b_name in std::iter::once(&b.name).chain(&b.aliases) {
+                        if a_name == b_name {
+                            ambiguities.push(Ambiguity::DuplicateSubcommand {
+                                command: self.name.clone(),
+                                name: a_name.clone(),
+                            });
+                        } else if let Some((prefix, name)) = prefix_pair(a_name, b_name) {
+                            ambiguities.push(Ambiguity::SubcommandPrefix {
+                                command: self.name.clone(),
+                                prefix: prefix.to_owned(),
+                                name: name.to_owned(),
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let mut flags: Vec<&String> = self
+            .flags
+            .iter()
+            .chain(&self.value_flags)
+            .chain(&self.rest_flags)
+            .collect();
+        flags.sort();
+        flags.dedup();
+        for (i, a) in flags.iter().enumerate() {
+            for b in &flags[(i + 1)..] {
+                if let Some((prefix, flag)) = prefix_pair(a, b) {
+                    ambiguities.push(Ambiguity::FlagPrefix {
+                        command: self.name.clone(),
+                        prefix: prefix.to_owned(),
+                        flag: flag.to_owned(),
+                    });
+                }
+            }
+        }
+
+        for subcommand in &self.subcommands {
+            ambiguities.extend(subcommand.check_ambiguities());
+        }
+
+        ambiguities
+    }
+
+    /// Whether the token following `flag` is read as its value rather than as the next token.
+    ///
+    /// This holds for [value flags](Command::value_flag) and for [rest
+    /// flags](Command::rest_after_flag), which also take every token after that. Written as
+    /// `flag=value` a value flag doesn't consume the next token. Plain flags and unknown names
+    /// return `false`.
+    pub fn is_value_flag(&self, flag: &str) -> bool {
+        self.value_flags.contains(flag) || self.rest_flags.contains(flag)
+    }
+
+    fn is_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag)
+            || self.value_flags.contains(flag)
+            || self.rest_flags.contains(flag)
+    }
+
+    /// Spell the flag in `token` as registered when [`Command::ignore_flag_case`] is set,
+    /// keeping any `=value` as is.
+    fn registered_flag<'a>(&self, token: &'a str) -> Cow<'a, str> {
+        let flag = token.split('=').next().unwrap_or(token);
+        if !self.ignore_flag_case || self.is_flag(flag) {
+            return Cow::Borrowed(token);
+        }
+
+        let registered = self
+            .flags
+            .iter()
+            .chain(&self.value_flags)
+            .chain(&self.rest_flags)
+            .find(|name| name.eq_ignore_ascii_case(flag));
+        match registered {
+            Some(name) => Cow::Owned(format!("{}{}", name, &token[flag.len()..])),
+            None => Cow::Borrowed(token),
+        }
+    }
+
+    pub fn parse_from<I, T>(&self, iter: I) -> Result<ParsedCommand, Error>
+    where
+        I: Iterator<Item = T>,
+        T: Into<String>,
+    {
+        self.parse_tokens(&collect_tokens(iter))
+    }
+
+    /// Parse tokens that never start with the command name.
+    ///
+    /// Unlike [`parse_from`], a leading token equal to the command name is kept as an arg. This is
+    /// also how subcommands see the tokens following their name.
+    ///
+    /// [`parse_from`]: Command::parse_from
+    pub fn parse_args_only<I, T>(&self, iter: I) -> Result<ParsedCommand, Error>
+    where
+        I: Iterator<Item = T>,
+        T: Into<String>,
+    {
+        self.parse_args(&collect_tokens(iter))
+    }
+
+    /// Parse the `argv` WeeChat passes to a command callback, where `argv[0]` is the command as
+    /// typed, slash included.
+    ///
+    /// The first value is always taken as the name, even when it is an alias or doesn't match,
+    /// and the rest are parsed like [`parse_args_only`]. Spans refer to the values joined with
+    /// single spaces.
+    ///
+    /// [`parse_args_only`]: Command::parse_args_only
+    pub fn parse_argv(&self, argv: &[&str]) -> Result<ParsedCommand, Error> {
+        let tokens = collect_tokens(argv.iter().copied());
+
+        match argv.first() {
+            Some(name) if !name.is_empty() => {
+                let args = self.transform_tokens(&tokens[1..]);
+                let mut context = Context::new(false, self.max_depth);
+                self.parse_partial(&args, Some(tokens[0].span.clone()), &[], &mut context)
+                    .map(|(parsed, _)| parsed)
+            }
+            _ => self.parse_args(&tokens),
+        }
+    }
+
+    /// Parse a single command off the front of a token stream.
+    ///
+    /// The command ends as soon as its leading flags and declared args are filled: one token per
+    /// arg, or every remaining token for a rest arg. When a subcommand matches, the subcommand
+    /// decides where the command ends instead. The tokens after the end are returned unparsed,
+    /// ready to be parsed as the next command. [`max_args`] does not apply here.
+    ///
+    /// [`max_args`]: Command::max_args
+    pub fn parse_prefix<I, T>(&self, iter: I) -> Result<(ParsedCommand, Vec<String>), Error>
+    where
+        I: Iterator<Item = T>,
+        T: Into<String>,
+    {
+        let args = collect_tokens(iter);
+        let args = self.transform_tokens(&args);
+        let (name_span, start) = self.match_name(&args);
+
+        let mut context = Context::new(true, self.max_depth);
+        let (parsed, end) = self.parse_partial(&args[start..], name_span, &[], &mut context)?;
+        let rest = args[(start + end)..]
+            .iter()
+            .map(|arg| arg.value.clone())
+            .collect();
+        Ok((parsed, rest))
+    }
+
+    /// Parse pre-split tokens into a reusable buffer.
+    ///
+    /// The buffer keeps its token storage between calls, so repeatedly parsing similar input, as
+    /// autocompletion does on every keystroke, does not allocate for the tokens again. The
+    /// returned command still owns the values it holds. Tokens are treated like in
+    /// [`parse_from`](Command::parse_from).
+    pub fn parse_into<'b>(
+        &self,
+        tokens: &[&str],
+        out: &'b mut ParsedCommandBuf,
+    ) -> Result<&'b ParsedCommand, Error> {
+        tokenizer::refill(&mut out.tokens, tokens);
+        let parsed = self.parse_tokens(&out.tokens)?;
+
+        Ok(out.parsed.insert(parsed))
+    }
+
+    /// Parse tokens, skipping the command name if it is the first token.
+    fn parse_tokens(&self, args: &[Token]) -> Result<ParsedCommand, Error> {
+        self.parse_tokens_with(args, &[])
+    }
+
+    /// Like [`Command::parse_tokens`], with `preset` flags set before parsing.
+    fn parse_tokens_with(&self, args: &[Token], preset: &[&str]) -> Result<ParsedCommand, Error> {
+        let args = self.transform_tokens(args);
+        let (name_span, start) = self.match_name(&args);
+        let mut context = Context::new(false, self.max_depth);
+        self.parse_partial(&args[start..], name_span, preset, &mut context)
+            .map(|(parsed, _)| parsed)
+    }
+
+    /// Run the [`Command::pre_tokenize`] hook, keeping the tokens as they are if it changes nothing.
+    fn transform_tokens<'t>(&self, tokens: &'t [Token]) -> Cow<'t, [Token]> {
+        let hook = match self.pre_tokenize {
+            Some(hook) => hook,
+            None => return Cow::Borrowed(tokens),
+        };
+
+        let values = hook(tokens.iter().map(|token| token.value.clone()).collect());
+        if values.iter().eq(tokens.iter().map(|token| &token.value)) {
+            Cow::Borrowed(tokens)
+        } else {
+            Cow::Owned(tokenizer::from_values(values.into_iter()))
+        }
+    }
+
+    /// Match the command name against the first tokens, returning where it was and how many
+    /// tokens it used.
+    fn match_name(&self, args: &[Token]) -> (Option<Range<usize>>, usize) {
+        match self.find_name(args) {
+            Some((span, count)) => (Some(span), count),
+            None => (None, 0),
+        }
+    }
+
+    /// Find the subcommand named by the leading tokens, returning where its name was and how many
+    /// tokens it used.
+    fn find_subcommand(&self, args: &[Token]) -> Result<Option<SubcommandName<'_>>, Error> {
+        let exact = self.subcommands.iter().find_map(|subcommand| {
+            subcommand
+                .find_name(args)
+                .map(|(span, count)| (subcommand, span, count))
+        });
+        if exact.is_some() {
+            return Ok(exact);
+        }
+
+        let token = match args.first() {
+            Some(token)
+                if self.abbreviate_subcommands && !token.literal && !token.value.is_empty() =>
+            {
+                token
+            }
+            _ => return Ok(None),
+        };
+
+        let candidates: Vec<&Command> = self
+            .subcommands
+            .iter()
+            .filter(|subcommand| {
+                std::iter::once(&subcommand.name)
+                    .chain(&subcommand.aliases)
+                    .any(|name| name.starts_with(token.value.as_str()))
+            })
+            .collect();
+
+        match candidates.as_slice() {
+            [] => Ok(None),
+            [subcommand] => Ok(Some((subcommand, token.span.clone(), 1))),
+            _ => Err(Error::AmbiguousSubcommand {
+                input: token.value.clone(),
+                candidates: candidates
+                    .iter()
+                    .map(|subcommand| subcommand.name.clone())
+                    .collect(),
+                span: token.span.clone(),
+            }),
+        }
+    }
+
+    /// Match the name or any alias against the leading tokens.
+    fn find_name(&self, args: &[Token]) -> Option<(Range<usize>, usize)> {
+        std::iter::once(&self.name)
+            .chain(&self.aliases)
+            .find_map(|name| match_words(args, name, !self.exact_name))
+    }
+
+    fn parse_args(&self, args: &[Token]) -> Result<ParsedCommand, Error> {
+        let args = self.transform_tokens(args);
+        let mut context = Context::new(false, self.max_depth);
+        self.parse_partial(&args, None, &[], &mut context)
+            .map(|(parsed, _)| parsed)
+    }
+
+    /// Parse tokens following the command name, returning how many tokens were used.
+    ///
+    /// The `preset` flags are set as if given before the first token.
+    fn parse_partial(
+        &self,
+        args: &[Token],
+        name_span: Option<Range<usize>>,
+        preset: &[&str],
+        context: &mut Context,
+    ) -> Result<(ParsedCommand, usize), Error> {
+        let prefix = context.prefix;
+        let mut state = FlagState {
+            flags: preset.iter().map(|&flag| flag.to_owned()).collect(),
+            ..FlagState::default()
+        };
+
+        let mut subcommand_match = None;
+        let mut parsed_args = Vec::new();
+        let mut pos = 0;
+
+        // Flags may come before the shared args too, so they aren't taken as their values.
+        let mut flags_ended = false;
+        if !self.opaque && !self.shared_args.is_empty() {
+            (pos, flags_ended) = self.parse_flags(args, pos, &mut state, context)?;
+        }
+
+        for rule in &self.shared_args {
+            let is_subcommand = !matches!(self.find_subcommand(&args[pos..]), Ok(None));
+
+            if pos == args.len() || (is_subcommand && !rule.required) {
+                if rule.is_required(&state.flags) {
+                    context.report(Error::RequiredArgMissing(rule.name.clone()))?;
+                }
+                parsed_args.extend(rule.default_arg());
+                continue;
+            }
+
+            parsed_args.push(ParsedArg {
+                name: rule.name.clone(),
+                value: rule.accept(
+                    self.trim_value(args[pos].value.clone(), &args[pos..=pos]),
+                    &args[pos].span,
+                    context,
+                )?,
+                inherited: false,
+                defaulted: false,
+                span: args[pos].span.clone(),
+            });
+            pos += 1;
+        }
+
+        if self.opaque {
+            let parsed = ParsedCommand {
+                command: self.name.clone(),
+                command_span: name_span,
+                flags: state.flags,
+                flag_values: state.values,
+                flag_occurrences: state.occurrences,
+                incomplete: state.incomplete,
+                args: parsed_args,
+                raw_tokens: args[pos..].iter().map(|arg| arg.value.clone()).collect(),
+                subcommand_match,
+                flattened: self.flatten,
+                default_on: self.default_on.clone(),
+                priority: self.priority,
+                remainder_start: args.get(pos).map(|arg| arg.span.start),
+                pairs: Vec::new(),
+                declared: self.arg_decls(),
+                multi_flags: self.multi_flags.clone(),
+            };
+            self.run_validators(&parsed, context)?;
+            return Ok((parsed, args.len()));
+        }
+
+        if !flags_ended {
+            (pos, flags_ended) = self.parse_flags(args, pos, &mut state, context)?;
+        }
+        let remainder_start = args.get(pos).map(|arg| arg.span.start);
+
+        // Flags before the subcommand name belong to this command.
+        let found = match self.find_subcommand(&args[pos..]) {
+            Ok(found) => found,
+            Err(error) => {
+                context.report(error)?;
+                None
+            }
+        };
+
+        if let Some((subcommand, span, count)) = found {
+            if context.depth == 0 {
+                context.report(Error::MaxDepthExceeded)?;
+            } else {
+                context.depth -= 1;
+                let reported = context.errors.as_ref().map_or(0, Vec::len);
+                let result = subcommand
+                    .parse_partial(&args[(pos + count)..], Some(span), &[], context)
+                    .map_err(|error| error.in_subcommand(&subcommand.name));
+                context.depth += 1;
+                if let Some(errors) = &mut context.errors {
+                    for error in &mut errors[reported..] {
+                        *error = error.clone().in_subcommand(&subcommand.name);
+                    }
+                }
+
+                let (mut parsed, used) = result?;
+                parsed.inherit(&parsed_args);
+                // Root args are filled from what the subcommand leaves.
+                pos += count + used;
+                subcommand_match = Some(Box::new((subcommand.name.clone(), parsed)));
+            }
+        }
+
+        // Greedy args can be followed by more flags, so the groups are checked after the args.
+        let greedy = self.args.iter().any(|rule| rule.until_flag);
+        if !greedy {
+            self.check_flag_groups(&state.flags, context)?;
+        }
+
+        let got = args.len() - pos;
+        if subcommand_match.is_none() && got < self.min_args {
+            context.report(Error::TooFewArgs {
+                min: self.min_args,
+                got,
+            })?;
+        }
+
+        if !prefix && subcommand_match.is_none() {
+            let has_rest = self.args.iter().any(|rule| rule.rest || rule.until_flag);
+            let max = match (self.max_args.filter(|_| !has_rest), self.range_max) {
+                (Some(cap), Some(range)) => Some(cap.min(range)),
+                (cap, range) => cap.or(range),
+            };
+            if let Some(max) = max.filter(|&max| got > max) {
+                context.report(Error::TooManyArgs { max, got })?;
+            }
+        }
+
+        if self.no_flags && !prefix && !flags_ended && subcommand_match.is_none() {
+            let flag = args[pos..].iter().find(|arg| {
+                !arg.literal
+                    && arg.value.len() > 1
+                    && arg.value.starts_with('-')
+                    && arg.value.parse::<f64>().is_err()
+            });
+            if let Some(flag) = flag {
+                context.report(Error::UnexpectedFlag(flag.value.clone(), flag.span.clone()))?;
+            }
+        }
+
+        let mut pairs = Vec::new();
+        let rules: &[ArgRule] = if self.args_without_subcommand && subcommand_match.is_some() {
+            &[]
+        } else {
+            &self.args
+        };
+
+        for rule in rules {
+            let value = if rule.rest || rule.until_flag {
+                let end = if rule.until_flag && !flags_ended {
+                    args[pos..]
+                        .iter()
+                        .position(|arg| self.starts_flag(arg))
+                        .map_or(args.len(), |index| pos + index)
+                } else {
+                    args.len()
+                };
+                let rest = &args[pos..end];
+                pos = end;
+                if rule.pairs {
+                    for token in rest {
+                        match token.value.split_once('=') {
+                            Some((key, value)) if !key.is_empty() => {
+                                pairs.push((key.to_owned(), value.to_owned()))
+                            }
+                            _ => context.report(Error::InvalidKeyValue(
+                                token.value.clone(),
+                                token.span.clone(),
+                            ))?,
+                        }
+                    }
+                }
+                if rule.until_flag && !flags_ended {
+                    pos = self.parse_flags(args, pos, &mut state, context)?.0;
+                }
+                match (rest.first(), rest.last()) {
+                    (Some(first), Some(last)) => Some((
+                        self.trim_value(join_values(rest), rest),
+                        first.span.start..last.span.end,
+                    )),
+                    _ => None,
+                }
+            } else {
+                args.get(pos).map(|arg| {
+                    pos += 1;
+                    let value = self.trim_value(arg.value.clone(), std::slice::from_ref(arg));
+                    (value, arg.span.clone())
+                })
+            };
+
+            match value {
+                Some((value, span)) => parsed_args.push(ParsedArg {
+                    name: rule.name.clone(),
+                    value: rule.accept(value, &span, context)?,
+                    inherited: false,
+                    defaulted: false,
+                    span,
+                }),
+                None => {
+                    if rule.is_required(&state.flags) {
+                        context.report(Error::RequiredArgMissing(rule.name.clone()))?;
+                    }
+                    parsed_args.extend(rule.default_arg());
+                }
+            };
+        }
+
+        if greedy {
+            self.check_flag_groups(&state.flags, context)?;
+        }
+
+        if self.deny_extra_args && !prefix && subcommand_match.is_none() {
+            if let Some(extra) = args.get(pos) {
+                context.report(Error::UnexpectedArg {
+                    value: extra.value.clone(),
+                    position: parsed_args.len(),
+                    span: extra.span.clone(),
+                })?;
+            }
+        }
+
+        let parsed = ParsedCommand {
+            command: self.name.clone(),
+            command_span: name_span,
+            flags: state.flags,
+            flag_values: state.values,
+            flag_occurrences: state.occurrences,
+            incomplete: state.incomplete,
+            args: parsed_args,
+            raw_tokens: Vec::new(),
+            subcommand_match,
+            flattened: self.flatten,
+            default_on: self.default_on.clone(),
+            priority: self.priority,
+            remainder_start,
+            pairs,
+            declared: self.arg_decls(),
+            multi_flags: self.multi_flags.clone(),
+        };
+        self.run_validators(&parsed, context)?;
+        Ok((parsed, pos))
+    }
+
+    /// Run the [`Command::validate_with`] checks on a parsed command.
+    fn run_validators(&self, parsed: &ParsedCommand, context: &mut Context) -> Result<(), Error> {
+        for check in &self.validators {
+            if let Err(message) = check(parsed) {
+                context.report(Error::ValidationFailed(message))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Parse the flags starting at `pos`, returning where they end and whether a `--` ended them.
+    fn parse_flags(
+        &self,
+        args: &[Token],
+        mut pos: usize,
+        state: &mut FlagState,
+        context: &mut Context,
+    ) -> Result<(usize, bool), Error> {
+        while let Some(Token {
+            value: arg,
+            literal: false,
+            span,
+        }) = args.get(pos)
+        {
+            if arg == "--" {
+                return Ok((pos + 1, true));
+            }
+
+            if let Some(detect) = self.flag_detector {
+                if !detect(arg) {
+                    break;
+                }
+            }
+
+            let registered = self.registered_flag(arg);
+            let key = registered.as_ref();
+            let (name, value, span) = if self.flags.contains(key) {
+                pos += 1;
+                (key, None, span.clone())
+            } else if let Some((flag, value)) = self.split_value_flag(key) {
+                pos += 1;
+                (flag, Some(value.to_owned()), span.clone())
+            } else if (self.value_flags.contains(key) || self.rest_flags.contains(key))
+                && pos + 1 == args.len()
+                || self.value_flags.contains(key)
+                    && args.get(pos + 1).is_some_and(|next| self.starts_flag(next))
+                || self.multi_flags.contains(key)
+                    && args.get(pos + 1).is_some_and(|next| self.ends_values(next))
+            {
+                context.report(Error::FlagValueMissing(arg.clone(), span.clone()))?;
+                state.incomplete.push(key.to_owned());
+                pos += 1;
+                (key, None, span.clone())
+            } else if self.multi_flags.contains(key) {
+                let end = args[(pos + 1)..]
+                    .iter()
+                    .position(|next| self.ends_values(next))
+                    .map_or(args.len(), |index| pos + 1 + index);
+                let (last, values) = args[(pos + 1)..end].split_last().expect("checked above");
+
+                // Only the first value's span covers the flag.
+                let mut start = Some(span.start);
+                for value in values {
+                    if let Err(error) = self.check_flag_value(key, &value.value, &value.span) {
+                        context.report(error)?;
+                    }
+                    state.values.push((key.to_owned(), value.value.clone()));
+                    state.occurrences.push(FlagOccurrence {
+                        name: key.to_owned(),
+                        typed: arg[..key.len()].to_owned(),
+                        value: Some(value.value.clone()),
+                        span: start.take().unwrap_or(value.span.start)..value.span.end,
+                    });
+                }
+                pos = end;
+                let start = start.unwrap_or(last.span.start);
+                (key, Some(last.value.clone()), start..last.span.end)
+            } else if self.value_flags.contains(key) {
+                let value = &args[pos + 1];
+                pos += 2;
+                (key, Some(value.value.clone()), span.start..value.span.end)
+            } else if self.rest_flags.contains(key) {
+                let rest = &args[(pos + 1)..];
+                let last = &rest[rest.len() - 1];
+                pos = args.len();
+                (key, Some(join_values(rest)), span.start..last.span.end)
+            } else if self.flag_detector.is_some() {
+                pos += 1;
+                (arg.as_str(), None, span.clone())
+            } else if self.unknown_flags == Policy::Error && arg.len() > 1 && arg.starts_with('-') {
+                context.report(Error::UnknownFlag(arg.clone(), span.clone()))?;
+                pos += 1;
+                continue;
+            } else {
+                break;
+            };
+
+            if let Some(value) = &value {
+                if let Err(error) = self.check_flag_value(name, value, &span) {
+                    context.report(error)?;
+                }
+            }
+
+            state.flags.insert(name.to_owned());
+            if let Some(value) = &value {
+                state.values.push((name.to_owned(), value.clone()));
+            }
+            state.occurrences.push(FlagOccurrence {
+                name: name.to_owned(),
+                typed: arg[..name.len()].to_owned(),
+                value,
+                span,
+            });
+        }
+
+        Ok((pos, false))
+    }
+
+    /// Whether the values of a [`Command::multi_value_flag`] stop before this token.
+    fn ends_values(&self, token: &Token) -> bool {
+        !token.literal && token.value == "--" || self.starts_flag(token)
+    }
+
+    /// Check the value of a [`Command::int_value_flag`].
+    fn check_flag_value(&self, flag: &str, value: &str, span: &Range<usize>) -> Result<(), Error> {
+        let range = match self.int_flags.iter().find(|(name, _)| name == flag) {
+            Some((_, range)) => range,
+            None => return Ok(()),
+        };
+
+        let message = match value.parse::<i64>() {
+            Ok(number) if range.contains(&number) => return Ok(()),
+            Ok(_) => format!("must be between {} and {}", range.start(), range.end()),
+            Err(_) => "must be a number".to_owned(),
+        };
+        Err(Error::InvalidFlagValue {
+            flag: flag.to_owned(),
+            message,
+            span: span.clone(),
+        })
+    }
+
+    /// Apply [`Command::trim_args`] to a value read from `tokens`, leaving quoted ends alone.
+    fn trim_value(&self, value: String, tokens: &[Token]) -> String {
+        if !self.trim_args {
+            return value;
+        }
+
+        let mut trimmed = value.as_str();
+        if tokens.first().is_some_and(|token| !token.literal) {
+            trimmed = trimmed.trim_start();
+        }
+        if tokens.last().is_some_and(|token| !token.literal) {
+            trimmed = trimmed.trim_end();
+        }
+
+        if trimmed.len() == value.len() {
+            value
+        } else {
+            trimmed.to_owned()
+        }
+    }
+
+    fn arg_decls(&self) -> Vec<ArgDecl> {
+        self.shared_args
+            .iter()
+            .chain(&self.args)
+            .map(|rule| ArgDecl {
+                name: rule.name.clone(),
+                default: rule.default.clone(),
+                required: rule.required,
+                until_flag: rule.until_flag,
+            })
+            .collect()
+    }
+
+    /// Whether a greedy [`Command::arg_until_flag`] or a value flag stops at this token.
+    fn starts_flag(&self, token: &Token) -> bool {
+        if token.literal {
+            return false;
+        }
+
+        let value = self.registered_flag(&token.value);
+        match self.flag_detector {
+            Some(detect) => detect(&value),
+            None => self.is_flag(&value) || self.split_value_flag(&value).is_some(),
+        }
+    }
+
+    fn check_flag_groups(
+        &self,
+        flags: &HashSet<String>,
+        context: &mut Context,
+    ) -> Result<(), Error> {
+        for group in &self.exclusive_groups {
+            let present: Vec<String> = group
+                .iter()
+                .filter(|flag| flags.contains(*flag))
+                .cloned()
+                .collect();
+            if present.len() > 1 {
+                context.report(Error::ConflictingFlags(present))?;
+            }
+        }
+
+        for group in &self.required_groups {
+            if !group.iter().any(|flag| flags.contains(flag)) {
+                context.report(Error::MissingRequiredGroup(group.clone()))?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Split a `flag=value` token, if `flag` is a registered value flag.
+    fn split_value_flag<'a>(&self, token: &'a str) -> Option<(&'a str, &'a str)> {
+        let index = token.find('=')?;
+        let flag = &token[..index];
+
+        if self.value_flags.contains(flag) {
+            Some((flag, &token[(index + 1)..]))
+        } else {
+            None
+        }
+    }
+
+    pub fn parse(&self, input: &str) -> Result<ParsedCommand, Error> {
+        self.parse_with_preset(input, &[])
+    }
+
+    /// Parse with `preset_flags` already set, as a wrapper that always enables a flag would.
+    ///
+    /// [`ParsedCommand::has_flag`] reports both preset and parsed flags. Preset flags count
+    /// towards [`Command::require_one_of`] and [`Command::exclusive_flags`] like given ones, so a
+    /// preset flag and a given flag from the same exclusive group fail with
+    /// [`Error::ConflictingFlags`]. They don't apply to subcommands.
+    pub fn parse_with_preset(
+        &self,
+        input: &str,
+        preset_flags: &[&str],
+    ) -> Result<ParsedCommand, Error> {
+        if self.allow_quotes {
+            self.parse_tokens_with(&tokenizer::tokenize(input)?, preset_flags)
+        } else {
+            self.parse_tokens_with(&tokenizer::split(input), preset_flags)
+        }
+    }
+
+    /// Like [`Command::parse`], but `None` when the input doesn't start with the name or an alias.
+    ///
+    /// This tells "not this command" apart from "this command with bad args", while `parse`
+    /// treats a missing name as omitted.
+    pub fn try_parse(&self, input: &str) -> Option<Result<ParsedCommand, Error>> {
+        let tokens = if self.allow_quotes {
+            match tokenizer::tokenize(input) {
+                Ok(tokens) => tokens,
+                Err(error) => {
+                    let tokens = tokenizer::split(input);
+                    return self
+                        .find_name(&self.transform_tokens(&tokens))
+                        .map(|_| Err(error));
+                }
+            }
+        } else {
+            tokenizer::split(input)
+        };
+
+        let tokens = self.transform_tokens(&tokens);
+        let (name_span, start) = self.find_name(&tokens)?;
+        let mut context = Context::new(false, self.max_depth);
+        let parsed = self
+            .parse_partial(&tokens[start..], Some(name_span), &[], &mut context)
+            .map(|(parsed, _)| parsed);
+        Some(parsed)
+    }
+
+    /// Like [`Command::parse`], keeping a reference to `input` for
+    /// [`ParsedInput::remainder`].
+    pub fn parse_borrowed<'a>(&self, input: &'a str) -> Result<ParsedInput<'a>, Error> {
+        Ok(ParsedInput {
+            parsed: self.parse(input)?,
+            input,
+        })
+    }
+
+    /// Parse as much as possible, returning every problem next to the result instead of failing.
+    ///
+    /// Missing required args, invalid values, flag group violations and the like don't stop the
+    /// parse, the affected args are left out or kept as given. Unbalanced quotes fall back to
+    /// splitting on spaces. This is meant for showing what was parsed so far while typing.
+    pub fn parse_lossy(&self, input: &str) -> (ParsedCommand, Vec<Error>) {
+        let mut errors = Vec::new();
+        let tokens = if self.allow_quotes {
+            tokenizer::tokenize(input).unwrap_or_else(|error| {
+                errors.push(error);
+                tokenizer::split(input)
+            })
+        } else {
+            tokenizer::split(input)
+        };
+
+        let tokens = self.transform_tokens(&tokens);
+        let (name_span, start) = self.match_name(&tokens);
+        let mut context = Context {
+            errors: Some(errors),
+            ..Context::new(false, self.max_depth)
+        };
+        let (parsed, _) = self
+            .parse_partial(&tokens[start..], name_span, &[], &mut context)
+            .expect("lossy parsing doesn't fail");
+
+        (parsed, context.errors.unwrap_or_default())
+    }
+
+    /// Parse several invocations separated by `;`, like `/say hi; /say bye`.
+    ///
+    /// With [`Command::allow_quotes`] a `;` inside quotes doesn't separate, and an unbalanced
+    /// quote fails with [`Error::UnbalancedQuote`]. Empty invocations are skipped.
+    pub fn parse_many(&self, input: &str) -> Result<Vec<ParsedCommand>, Error> {
+        tokenizer::split_commands(input, ';', self.allow_quotes)?
+            .into_iter()
+            .map(|segment| self.parse(segment))
+            .collect()
+    }
+
+    /// Check that `other` parses every input the same way as this command.
+    ///
+    /// Successful parses have to be equal [`ParsedCommand`]s, and failures have to give the same
+    /// error message. This is meant for tests when migrating between definitions.
+    pub fn behaves_like(&self, other: &Command, inputs: &[&str]) -> bool {
+        inputs
+            .iter()
+            .all(|input| match (self.parse(input), other.parse(input)) {
+                (Ok(a), Ok(b)) => a == b,
+                (Err(a), Err(b)) => a.to_string() == b.to_string(),
+                _ => false,
+            })
+    }
+}
+
+/// The `no-` form of a flag, `--color` becomes `--no-color`.
+fn negated_flag(flag: &str) -> String {
+    let name = flag.trim_start_matches('-');
+    format!("{}no-{}", &flag[..(flag.len() - name.len())], name)
+}
+
+/// The shortest prefix of `name` that isn't also a prefix of any of `others`.
+///
+/// The whole name is returned if there is none, as an exact name wins over abbreviations.
+fn shortest_prefix<'a>(name: &'a str, others: &[&str]) -> &'a str {
+    name.char_indices()
+        .map(|(start, c)| &name[..start + c.len_utf8()])
+        .find(|prefix| !others.iter().any(|other| other.starts_with(prefix)))
+        .unwrap_or(name)
+}
+
+/// Order two distinct names as `(prefix, name)` if one is a prefix of the other.
+fn prefix_pair<'a>(a: &'a str, b: &'a str) -> Option<(&'a str, &'a str)> {
+    if b.starts_with(a) {
+        Some((a, b))
+    } else if a.starts_with(b) {
+        Some((b, a))
+    } else {
+        None
+    }
+}
+
+/// Match a name against the leading tokens, returning the span and number of tokens used.
+///
+/// A name containing spaces, like `"server add"`, has to match one word per token. Quoted tokens
+/// never match.
+///
+/// With `extra_slashes` the first token may start with more slashes than the name.
+fn match_words(args: &[Token], name: &str, extra_slashes: bool) -> Option<(Range<usize>, usize)> {
+    let mut span: Option<Range<usize>> = None;
+    let mut count = 0;
+
+    for word in name.split(' ').filter(|word| !word.is_empty()) {
+        let matches = |value: &str| {
+            value == word
+                || extra_slashes
+                    && count == 0
+                    && word.starts_with('/')
+                    && value.ends_with(word)
+                    && value[..(value.len() - word.len())]
+                        .bytes()
+                        .all(|b| b == b'/')
+        };
+
+        match args.get(count) {
+            Some(arg) if !arg.literal && matches(&arg.value) => {
+                let start = span.map_or(arg.span.start, |span| span.start);
+                span = Some(start..arg.span.end);
+                count += 1;
+            }
+            _ => return None,
+        }
+    }
+
+    span.map(|span| (span, count))
+}
+
+/// Join the token values with single spaces.
+fn join_values(tokens: &[Token]) -> String {
+    let values: Vec<&str> = tokens.iter().map(|arg| arg.value.as_str()).collect();
+    values.join(" ")
+}
+
+fn collect_tokens<I, T>(iter: I) -> Vec<Token>
+where
+    I: Iterator<Item = T>,
+    T: Into<String>,
+{
+    tokenizer::from_values(iter.map(Into::into))
+}
+
+/// A [`ParsedCommand`] that keeps the input it was parsed from, see [`Command::parse_borrowed`].
+#[derive(Debug, Clone)]
+pub struct ParsedInput<'a> {
+    parsed: ParsedCommand,
+    input: &'a str,
+}
+
+impl<'a> ParsedInput<'a> {
+    /// The input after the names and flags of the deepest matched subcommand, with its original
+    /// spacing and quotes.
+    ///
+    /// For `/say -v hello   world` this is `hello   world`. `None` if nothing follows.
+    pub fn remainder(&self) -> Option<&'a str> {
+        let mut parsed = &self.parsed;
+        while let Some((_, subcommand)) = parsed.subcommand_match.as_deref() {
+            parsed = subcommand;
+        }
+
+        parsed
+            .remainder_start
+            .and_then(|start| self.input.get(start..))
+    }
+
+    /// The text of an arg as written in the input, with its original spacing and quotes.
+    ///
+    /// A rest arg returns everything from its first token to its last, unlike
+    /// [`arg`](ParsedCommand::arg) which joins the tokens with single spaces. Defaulted args
+    /// return `None`, as do args whose span doesn't fit the input.
+    pub fn arg_raw(&self, name: &str) -> Option<&'a str> {
+        self.input.get(self.parsed.arg_span(name)?)
+    }
+
+    pub fn input(&self) -> &'a str {
+        self.input
+    }
+
+    pub fn into_inner(self) -> ParsedCommand {
+        self.parsed
+    }
+}
+
+impl std::ops::Deref for ParsedInput<'_> {
+    type Target = ParsedCommand;
+
+    fn deref(&self) -> &ParsedCommand {
+        &self.parsed
+    }
+}
+
+/// Reusable storage for [`Command::parse_into`].
+#[derive(Debug, Default)]
+pub struct ParsedCommandBuf {
+    tokens: Vec<Token>,
+    parsed: Option<ParsedCommand>,
+}
+
+impl ParsedCommandBuf {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The result of the last successful parse.
+    pub fn get(&self) -> Option<&ParsedCommand> {
+        self.parsed.as_ref()
+    }
+}
+
+#[derive(Debug, Clone)]
+struct ParsedArg {
+    pub name: String,
+    pub value: String,
+    /// Copied from a parent's shared arg.
+    pub inherited: bool,
+    /// Filled from a default instead of the input.
+    pub defaulted: bool,
+    pub span: Range<usize>,
+}
+
+impl PartialEq for ParsedArg {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && self.value == other.value && self.inherited == other.inherited
+    }
+}
+
+impl Eq for ParsedArg {}
+
+impl Hash for ParsedArg {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.name.hash(state);
+        self.value.hash(state);
+        self.inherited.hash(state);
+    }
+}
+
+/// A subcommand with the span of its name in the input and how many tokens the name used.
+type SubcommandName<'a> = (&'a Command, Range<usize>, usize);
+
+/// A check added with [`Command::validate_with`].
+type Validator = fn(&ParsedCommand) -> Result<(), String>;
+
+/// Settings shared by every level of a single parse.
+struct Context {
+    /// Extra tokens are expected to belong to a following command.
+    prefix: bool,
+    /// How many more levels of subcommands may be entered.
+    depth: usize,
+    /// Errors collected by [`Command::parse_lossy`] instead of failing.
+    errors: Option<Vec<Error>>,
+}
+
+impl Context {
+    fn new(prefix: bool, depth: usize) -> Self {
+        Self {
+            prefix,
+            depth,
+            errors: None,
+        }
+    }
+
+    /// Fail with `error`, or note it and carry on in lossy mode.
+    fn report(&mut self, error: Error) -> Result<(), Error> {
+        match &mut self.errors {
+            Some(errors) => {
+                errors.push(error);
+                Ok(())
+            }
+            None => Err(error),
+        }
+    }
+}
+
+/// The flags collected while parsing a single command.
+#[derive(Default)]
+struct FlagState {
+    flags: HashSet<String>,
+    values: Vec<(String, String)>,
+    occurrences: Vec<FlagOccurrence>,
+    incomplete: Vec<String>,
+}
+
+/// A single flag as it appeared in the input.
+#[derive(Debug, Clone)]
+struct FlagOccurrence {
+    pub name: String,
+    /// The flag as written, which differs from `name` with [`Command::ignore_flag_case`].
+    pub typed: String,
+    pub value: Option<String>,
+    /// Covers the flag and its value.
+    pub span: Range<usize>,
+}
+
+/// An arg as declared on the command.
+#[derive(Debug, Clone)]
+struct ArgDecl {
+    name: String,
+    default: Option<String>,
+    required: bool,
+    until_flag: bool,
+}
+
+/// Everything known about one arg of a parsed command, see [`ParsedCommand::arg_info`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ArgInfo<'a> {
+    /// The value read by [`ParsedCommand::arg`], given or defaulted.
+    pub value: Option<&'a str>,
+    /// The default set with [`Command::arg_default`].
+    pub default: Option<&'a str>,
+    pub required: bool,
+    /// The value came from the input rather than the default.
+    pub supplied: bool,
+}
+
+/// A flag or arg of a parsed command, see [`ParsedCommand::get_all`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    /// A flag without a value.
+    Flag,
+    /// An arg, or a value flag given once.
+    Single(String),
+    /// Every value of a value flag given more than once.
+    Multi(Vec<String>),
+}
+
+/// Two parsed commands are equal if they hold the same flags, values and subcommands, where in
+/// the input these appeared is not compared.
+#[derive(Debug, Clone)]
+pub struct ParsedCommand {
+    command: String,
+    command_span: Option<Range<usize>>,
+    flags: HashSet<String>,
+    flag_values: Vec<(String, String)>,
+    flag_occurrences: Vec<FlagOccurrence>,
+    /// Value flags given without a value, see [`ParsedCommand::incomplete_value_flags`].
+    incomplete: Vec<String>,
+    args: Vec<ParsedArg>,
+    raw_tokens: Vec<String>,
+    subcommand_match: Option<Box<(String, ParsedCommand)>>,
+    /// Flags and args are also readable from the parent, see [`Command::flatten_subcommand`].
+    flattened: bool,
+    /// Flags that [`ParsedCommand::bool_flag`] reports as set unless negated.
+    default_on: Vec<String>,
+    /// Where the first token after the name and flags starts.
+    remainder_start: Option<usize>,
+    /// The split tokens of a [`Command::kv_arg`].
+    pairs: Vec<(String, String)>,
+    /// The shared args and args of the command, for [`ParsedCommand::arg_info`].
+    declared: Vec<ArgDecl>,
+    /// Set with [`Command::lookup_priority`].
+    priority: Priority,
+    /// The [`Command::multi_value_flag`]s, so [`ParsedCommand::to_command_line`] can end them.
+    multi_flags: HashSet<String>,
+}
+
+impl PartialEq for ParsedCommand {
+    fn eq(&self, other: &Self) -> bool {
+        self.command == other.command
+            && self.flags == other.flags
+            && self.flag_values == other.flag_values
+            && self.args == other.args
+            && self.raw_tokens == other.raw_tokens
+            && self.subcommand_match == other.subcommand_match
+            && self.pairs == other.pairs
+    }
+}
+
+impl Eq for ParsedCommand {}
+
+impl Hash for ParsedCommand {
+    /// Hashes the same fields compared by `==`, with the flags sorted so the order they are
+    /// stored in doesn't matter.
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut flags: Vec<&String> = self.flags.iter().collect();
+        flags.sort_unstable();
+
+        self.command.hash(state);
+        flags.hash(state);
+        self.flag_values.hash(state);
+        self.args.hash(state);
+        self.raw_tokens.hash(state);
+        self.subcommand_match.hash(state);
+        self.pairs.hash(state);
+    }
+}
+
+impl ParsedCommand {
+    pub fn has_flag(&self, flag: &str) -> bool {
+        self.flags.contains(flag) || self.flattened().is_some_and(|sub| sub.has_flag(flag))
+    }
+
+    /// Whether a flag is on, taking [`Command::default_on_flag`] and `no-` forms into account.
+    ///
+    /// The last of `--color` and `--no-color` wins. If neither was given this is `true` for
+    /// default on flags and `false` otherwise.
+    pub fn bool_flag(&self, flag: &str) -> bool {
+        let negated = negated_flag(flag);
+
+        match self
+            .flag_occurrences
+            .iter()
+            .rev()
+            .find(|occurrence| occurrence.name == flag || occurrence.name == negated)
+        {
+            Some(occurrence) => occurrence.name == flag,
+            None if self.flags.contains(&negated) => false,
+            None => self.has_flag(flag) || self.default_on.iter().any(|name| name == flag),
+        }
+    }
+
+    /// The matched subcommand, if it was flattened into this command.
+    fn flattened(&self) -> Option<&ParsedCommand> {
+        self.subcommand_match
+            .as_deref()
+            .map(|(_, parsed)| parsed)
+            .filter(|parsed| parsed.flattened)
+    }
+
+    /// The value of a value flag.
+    ///
+    /// If the flag was given more than once the last value wins, see [`flag_values`] for all of
+    /// them.
+    ///
+    /// [`flag_values`]: ParsedCommand::flag_values
+    pub fn flag_value(&self, flag: &str) -> Option<&str> {
+        self.flag_values(flag).pop()
+    }
+
+    /// The value of a value flag, or `default` if it wasn't given.
+    ///
+    /// ```
+    /// use weechat_command_parser::Command;
+    ///
+    /// let command = Command::new("/join").value_flag("-server").flag("-quiet");
+    /// let matches = command.parse("/join -quiet").unwrap();
+    /// assert_eq!(matches.flag_value_or("-server", "libera"), "libera");
+    /// assert_eq!(matches.flag_value_or("-quiet", "none"), "none");
+    ///
+    /// let matches = command.parse("/join -server oftc").unwrap();
+    /// assert_eq!(matches.flag_value_or("-server", "libera"), "oftc");
+    /// ```
+    pub fn flag_value_or<'a>(&'a self, flag: &str, default: &'a str) -> &'a str {
+        self.flag_value(flag).unwrap_or(default)
+    }
+
+    /// Every value given for a value flag, in order of appearance.
+    pub fn flag_values(&self, flag: &str) -> Vec<&str> {
+        let values: Vec<&str> = self
+            .flag_values
+            .iter()
+            .filter(|(name, _)| name == flag)
+            .map(|(_, value)| value.as_ref())
+            .collect();
+
+        match self.flattened() {
+            Some(sub) if values.is_empty() => sub.flag_values(flag),
+            _ => values,
+        }
+    }
+
+    /// Parse the value of a value flag, `None` if it wasn't given or doesn't parse.
+    pub fn flag_as<T: FromStr>(&self, flag: &str) -> Option<T> {
+        self.flag_value(flag)?.parse().ok()
+    }
+
+    /// Split the last value of a flag on commas.
+    ///
+    /// Items are trimmed and empty items are skipped, so `--channels "rust, ,irc,"` gives
+    /// `["rust", "irc"]`.
+    pub fn flag_csv(&self, flag: &str) -> Option<Vec<&str>> {
+        self.flag_value(flag).map(|value| {
+            value
+                .split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .collect()
+        })
+    }
+
+    pub fn arg(&self, name: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .find(|arg| arg.name == name)
+            .map(|arg| arg.value.as_ref())
+            .or_else(|| self.flattened()?.arg(name))
+    }
+
+    /// Where the arg called `name` was in the input, `None` for defaulted args.
+    fn arg_span(&self, name: &str) -> Option<Range<usize>> {
+        match self.args.iter().find(|arg| arg.name == name) {
+            Some(arg) if arg.defaulted => None,
+            Some(arg) => Some(arg.span.clone()),
+            None => self.flattened()?.arg_span(name),
+        }
+    }
+
+    /// The `key=value` pairs of a [`Command::kv_arg`], in order of appearance.
+    pub fn kv_args(&self) -> Vec<(&str, &str)> {
+        match self.flattened() {
+            Some(sub) if self.pairs.is_empty() => sub.kv_args(),
+            _ => self
+                .pairs
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str()))
+                .collect(),
+        }
+    }
+
+    /// The value of an arg together with how it was declared, `None` if there is no such arg.
+    ///
+    /// Args inherited from a parent's shared args are reported as optional without a default.
+    pub fn arg_info(&self, name: &str) -> Option<ArgInfo<'_>> {
+        let parsed = self.args.iter().find(|arg| arg.name == name);
+
+        match self.declared.iter().find(|decl| decl.name == name) {
+            Some(decl) => Some(ArgInfo {
+                value: parsed.map(|arg| arg.value.as_str()),
+                default: decl.default.as_deref(),
+                required: decl.required,
+                supplied: parsed.is_some_and(|arg| !arg.defaulted),
+            }),
+            None => match parsed {
+                Some(arg) => Some(ArgInfo {
+                    value: Some(&arg.value),
+                    default: None,
+                    required: false,
+                    supplied: !arg.defaulted,
+                }),
+                None => self.flattened()?.arg_info(name),
+            },
+        }
+    }
+
+    /// Read an arg as a path, expanding a leading `~` to the home directory.
+    ///
+    /// Only `~` on its own or followed by `/` is expanded, using `HOME` or else `USERPROFILE`.
+    /// Other values, or all values when neither is set, are returned unchanged.
+    pub fn arg_as_path(&self, name: &str) -> Option<PathBuf> {
+        let value = self.arg(name)?;
+        let rest = match value.strip_prefix('~') {
+            Some(rest) if rest.is_empty() || rest.starts_with('/') => rest.trim_start_matches('/'),
+            _ => return Some(PathBuf::from(value)),
+        };
+
+        match std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE")) {
+            Some(home) => Some(Path::new(&home).join(rest)),
+            None => Some(PathBuf::from(value)),
+        }
+    }
+
+    /// Read an arg as a boolean.
+    ///
+    /// `true`/`false`, `yes`/`no`, `on`/`off` and `1`/`0` are accepted, ignoring case. Any other
+    /// value, or a missing arg, returns `None`.
+    pub fn arg_as_bool(&self, name: &str) -> Option<bool> {
+        match self.arg(name)?.to_ascii_lowercase().as_str() {
+            "true" | "yes" | "on" | "1" => Some(true),
+            "false" | "no" | "off" | "0" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Iterate over `(name, value)` pairs of the args that were given, in declaration order.
+    ///
+    /// Flags are not included.
+    pub fn iter(&self) -> Iter<'_> {
+        self.into_iter()
+    }
+
+    /// The flags given in the input, in order of first appearance and spelled as typed.
+    ///
+    /// Preset and default flags are not included.
+    pub fn flags(&self) -> Vec<&str> {
+        let mut flags = Vec::new();
+        for occurrence in &self.flag_occurrences {
+            if !flags.contains(&occurrence.typed.as_str()) {
+                flags.push(occurrence.typed.as_str());
+            }
+        }
+
+        flags
+    }
+
+    /// Every value flag with its values, in order of the flag's first appearance.
+    ///
+    /// The value flag analog of [`ParsedCommand::named_args`]. Flags without a value are left
+    /// out, as are the flags of a flattened subcommand.
+    pub fn flag_value_map(&self) -> Vec<(&str, Vec<&str>)> {
+        let mut map: Vec<(&str, Vec<&str>)> = Vec::new();
+        for (flag, value) in &self.flag_values {
+            match map.iter_mut().find(|(name, _)| name == flag) {
+                Some((_, values)) => values.push(value),
+                None => map.push((flag, vec![value])),
+            }
+        }
+
+        map
+    }
+
+    /// The value of the arg at `index`, in the same order as [`Command::arg_name_at`].
+    ///
+    /// The index refers to the declared args, so an optional arg that wasn't given is `None`
+    /// without shifting the ones after it. Args inherited from a parent are not counted.
+    pub fn arg_value_at(&self, index: usize) -> Option<&str> {
+        let name = &self.declared.get(index)?.name;
+        self.args
+            .iter()
+            .find(|arg| !arg.inherited && arg.name == *name)
+            .map(|arg| arg.value.as_ref())
+    }
+
+    /// How many of this command's declared args were given in the input.
+    ///
+    /// An empty quoted arg (`""`) counts as given. Args filled from defaults or inherited from a
+    /// parent's shared args are not counted.
+    pub fn arg_present_count(&self) -> usize {
+        self.args
+            .iter()
+            .filter(|arg| !arg.inherited && !arg.defaulted)
+            .count()
+    }
+
+    /// Whether the input had nothing after the command name.
+    ///
+    /// No flags, args or subcommand were given. Args filled from defaults or inherited from a
+    /// parent don't count.
+    pub fn is_empty(&self) -> bool {
+        self.flags.is_empty()
+            && self.raw_tokens.is_empty()
+            && self.subcommand_match.is_none()
+            && self.args.iter().all(|arg| arg.inherited || arg.defaulted)
+    }
+
+    pub fn args(&self) -> Vec<&str> {
+        self.args.iter().map(|arg| arg.value.as_ref()).collect()
+    }
+
+    /// Parse every arg as a `T`, in the same order as [`ParsedCommand::args`].
+    ///
+    /// Each value is parsed on its own, so `/roll 2 x` gives `[Ok(2), Err(..)]` rather than
+    /// failing as a whole.
+    pub fn args_as<T: FromStr>(&self) -> Vec<Result<T, T::Err>> {
+        self.args.iter().map(|arg| arg.value.parse()).collect()
+    }
+
+    /// Move the arg values out, in the same order as [`ParsedCommand::args`].
+    pub fn into_args(self) -> Vec<String> {
+        self.args.into_iter().map(|arg| arg.value).collect()
+    }
+
+    /// The `(name, value)` pairs of the args, in the same order as [`ParsedCommand::iter`].
+    pub fn named_args(&self) -> Vec<(&str, &str)> {
+        self.iter().collect()
+    }
+
+    /// The flag or arg called `name`, see [`ParsedCommand::get_all`].
+    ///
+    /// If both a flag and an arg have this name the [`Command::lookup_priority`] decides.
+    pub fn get(&self, name: &str) -> Option<Value> {
+        self.get_all()
+            .into_iter()
+            .find(|(found, _)| found == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Every flag and arg of this command in one list, for showing the whole result.
+    ///
+    /// Flags come first, preset flags sorted and then the others in the order they were first
+    /// given, followed by the args in declaration order. With [`Priority::ArgsFirst`] the args
+    /// come before the flags instead. Matched subcommands are not included.
+    pub fn get_all(&self) -> Vec<(String, Value)> {
+        let mut preset: Vec<&String> = self
+            .flags
+            .iter()
+            .filter(|flag| {
+                !self
+                    .flag_occurrences
+                    .iter()
+                    .any(|occurrence| occurrence.name == **flag)
+            })
+            .collect();
+        preset.sort_unstable();
+
+        let mut names: Vec<&String> = Vec::new();
+        for name in preset.into_iter().chain(
+            self.flag_occurrences
+                .iter()
+                .map(|occurrence| &occurrence.name),
+        ) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+
+        let flags = names.into_iter().map(|name| {
+            let mut values: Vec<String> = self
+                .flag_values
+                .iter()
+                .filter(|(flag, _)| flag == name)
+                .map(|(_, value)| value.clone())
+                .collect();
+            let value = match values.len() {
+                0 => Value::Flag,
+                1 => Value::Single(values.remove(0)),
+                _ => Value::Multi(values),
+            };
+            (name.clone(), value)
+        });
+        let args = self
+            .args
+            .iter()
+            .map(|arg| (arg.name.clone(), Value::Single(arg.value.clone())));
+
+        match self.priority {
+            Priority::FlagsFirst => flags.chain(args).collect(),
+            Priority::ArgsFirst => args.chain(flags).collect(),
+        }
+    }
+
+    /// Move the `(name, value)` pairs of the args out without cloning them.
+    pub fn into_named_args(self) -> Vec<(String, String)> {
+        self.into_iter().collect()
+    }
+
+    /// Add shared args from a parent to this command and its matched subcommands.
+    fn inherit(&mut self, shared: &[ParsedArg]) {
+        for arg in shared {
+            if !self.args.iter().any(|existing| existing.name == arg.name) {
+                self.args.push(ParsedArg {
+                    inherited: true,
+                    ..arg.clone()
+                });
+            }
+        }
+
+        if let Some(subcommand) = self.subcommand_match.as_deref_mut() {
+            subcommand.1.inherit(shared);
+        }
+    }
+
+    /// Rename the arg `from` to `to`, for handing the result to code using other names.
+    ///
+    /// Nothing changes and `false` is returned if this command has no arg `from`, declared or
+    /// given, or already has one called `to`. Subcommands are not renamed.
+    pub fn rename_arg(&mut self, from: &str, to: &str) -> bool {
+        let known = |name: &str| {
+            self.args.iter().any(|arg| arg.name == name)
+                || self.declared.iter().any(|decl| decl.name == name)
+        };
+        if !known(from) || known(to) {
+            return false;
+        }
+
+        for arg in self.args.iter_mut().filter(|arg| arg.name == from) {
+            arg.name = to.to_owned();
+        }
+        for decl in self.declared.iter_mut().filter(|decl| decl.name == from) {
+            decl.name = to.to_owned();
+        }
+
+        true
+    }
+
+    /// Fill the args that were not given with the defaults of `command`.
+    ///
+    /// Args that already have a value, including ones defaulted during parsing, are left alone, so
+    /// calling this for several commands applies their defaults in order of priority. Subcommands
+    /// are filled from the subcommand of `command` with the same name.
+    pub fn merge_defaults(&mut self, command: &Command) {
+        for rule in command.shared_args.iter().chain(&command.args) {
+            if !self.args.iter().any(|arg| arg.name == rule.name) {
+                self.args.extend(rule.default_arg());
+            }
+        }
+
+        if let Some((name, parsed)) = self.subcommand_match.as_deref_mut() {
+            if let Some(subcommand) = command
+                .subcommands
+                .iter()
+                .find(|subcommand| subcommand.name == *name)
+            {
+                parsed.merge_defaults(subcommand);
+            }
+        }
+    }
+
+    /// The value flags that were given without a value, in order.
+    ///
+    /// This only happens with [`Command::parse_lossy`], where a value flag at the end of the
+    /// input or right before another registered flag is kept without a value next to an
+    /// [`Error::FlagValueMissing`]. Other parses fail with that error instead, so this is empty
+    /// for them.
+    pub fn incomplete_value_flags(&self) -> &[String] {
+        &self.incomplete
+    }
+
+    /// The unparsed tokens captured by an opaque subcommand.
+    ///
+    /// This is empty for regular commands.
+    pub fn raw_tokens(&self) -> &[String] {
+        &self.raw_tokens
+    }
+
+    /// The part of the parsed input after the command name.
+    ///
+    /// `raw` should be the input given to [`Command::parse`]. It is returned whole if the name
+    /// wasn't given, so an arg equal to the name is never cut off.
+    pub fn rest<'a>(&self, raw: &'a str) -> &'a str {
+        match &self.command_span {
+            Some(span) => raw.get(span.end..).unwrap_or(raw),
+            None => raw,
+        }
+    }
+
+    pub fn subcommand(&self) -> Option<(&str, ParsedCommand)> {
+        self.subcommand_match
+            .as_deref()
+            .map(|(name, cmd)| (name.as_ref(), cmd.clone()))
+    }
+
+    /// The name of the matched subcommand, without cloning it like [`ParsedCommand::subcommand`].
+    pub fn subcommand_name(&self) -> Option<&str> {
+        self.subcommand_match
+            .as_deref()
+            .map(|(name, _)| name.as_ref())
+    }
+
+    pub fn command(&self) -> &str {
+        &self.command
+    }
+
+    /// Where the command name was in the input, `None` when it was omitted.
+    ///
+    /// A name made of several words covers all of them.
+    pub fn command_span(&self) -> Option<Range<usize>> {
+        self.command_span.clone()
+    }
+
+    /// Where the name of the matched subcommand was in the input.
+    pub fn subcommand_span(&self) -> Option<Range<usize>> {
+        self.subcommand_match
+            .as_deref()
+            .and_then(|(_, parsed)| parsed.command_span())
+    }
+
+    /// Rebuild a command line that parses back into an equal `ParsedCommand`.
+    ///
+    /// Flags come first, sorted, followed by value flags in their original order and then the
+    /// args. The values of a [`Command::multi_value_flag`] given in a row are written as one
+    /// group, and a `--` follows if it is the last flag. When args follow a
+    /// [`Command::arg_until_flag`], the flags are written after it instead so it stops at them.
+    /// Values are quoted where needed, so the command has to be parsed with
+    /// [`Command::allow_quotes`] enabled. Flags registered without a leading `-` can still be
+    /// confused with the first arg.
+    pub fn to_command_line(&self) -> String {
+        let mut line = self.command.clone();
+
+        let subcommand = self.subcommand_match.as_deref().map(|(_, parsed)| parsed);
+
+        if let Some(subcommand) = subcommand {
+            for arg in self.args.iter().filter(|arg| !arg.inherited) {
+                let shared = subcommand
+                    .args
+                    .iter()
+                    .any(|sub_arg| sub_arg.inherited && sub_arg.name == arg.name);
+                if shared {
+                    line.push(' ');
+                    line.push_str(&tokenizer::quote(&arg.value));
+                }
+            }
+        }
+
+        let mut flag_line = String::new();
+        let mut flags: Vec<&String> = self
+            .flags
+            .iter()
+            .filter(|flag| !self.flag_values.iter().any(|(name, _)| name == *flag))
+            .collect();
+        flags.sort();
+
+        for flag in flags {
+            flag_line.push(' ');
+            flag_line.push_str(flag);
+        }
+
+        let mut values = self.flag_values.iter().peekable();
+        let mut open = false;
+        while let Some((flag, value)) = values.next() {
+            flag_line.push(' ');
+            flag_line.push_str(flag);
+            flag_line.push(' ');
+            flag_line.push_str(&tokenizer::quote(value));
+
+            // The next flag ends the group, so only the last one needs a `--`.
+            open = self.multi_flags.contains(flag);
+            if open {
+                while let Some((_, value)) = values.next_if(|(next, _)| next == flag) {
+                    flag_line.push(' ');
+                    flag_line.push_str(&tokenizer::quote(value));
+                }
+            }
+        }
+        if open {
+            flag_line.push_str(" --");
+        }
+
+        if let Some(subcommand) = subcommand {
+            line.push_str(&flag_line);
+            line.push(' ');
+            line.push_str(&subcommand.to_command_line());
+            return line;
+        }
+
+        let mut args: Vec<&ParsedArg> = self.args.iter().filter(|arg| !arg.inherited).collect();
+        // The pairs come from the last arg, quoting it whole would make a single pair.
+        if !self.pairs.is_empty() {
+            args.pop();
+        }
+
+        // A greedy arg only stops at a flag, so the flags go right after it if more args follow.
+        let split = args
+            .iter()
+            .position(|arg| {
+                self.declared
+                    .iter()
+                    .any(|decl| decl.until_flag && decl.name == arg.name)
+            })
+            .map(|index| index + 1)
+            .filter(|&split| split < args.len() || !self.pairs.is_empty())
+            .unwrap_or(0);
+
+        let quoted = |line: &mut String, args: &[&ParsedArg]| {
+            for arg in args {
+                line.push(' ');
+                line.push_str(&tokenizer::quote(&arg.value));
+            }
+        };
+        quoted(&mut line, &args[..split]);
+        line.push_str(&flag_line);
+        quoted(&mut line, &args[split..]);
+
+        for value in &self.raw_tokens {
+            line.push(' ');
+            line.push_str(&tokenizer::quote(value));
+        }
+
+        for (key, value) in &self.pairs {
+            line.push(' ');
+            line.push_str(key);
+            line.push('=');
+            line.push_str(&tokenizer::quote(value));
+        }
+
+        line
+    }
+
+    /// A JSON object with the `command`, its sorted `flags`, the `args` by name and the nested
+    /// `subcommand`, which is `null` when there is none.
+    ///
+    /// Args inherited from a parent only appear on the parent.
+    pub fn to_json(&self) -> String {
+        let mut json = String::from("{\"command\":");
+        push_json_string(&mut json, &self.command);
+
+        let mut flags: Vec<&String> = self.flags.iter().collect();
+        flags.sort();
+        json.push_str(",\"flags\":[");
+        for (index, flag) in flags.into_iter().enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            push_json_string(&mut json, flag);
+        }
+
+        json.push_str("],\"args\":{");
+        for (index, arg) in self.args.iter().filter(|arg| !arg.inherited).enumerate() {
+            if index > 0 {
+                json.push(',');
+            }
+            push_json_string(&mut json, &arg.name);
+            json.push(':');
+            push_json_string(&mut json, &arg.value);
+        }
+
+        json.push_str("},\"subcommand\":");
+        match self.subcommand_match.as_deref() {
+            Some((_, parsed)) => json.push_str(&parsed.to_json()),
+            None => json.push_str("null"),
+        }
+        json.push('}');
+
+        json
+    }
+}
+
+/// Append `value` to `json` as a quoted JSON string.
+fn push_json_string(json: &mut String, value: &str) {
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c.is_control() => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+}
+
+/// Borrowing iterator over the `(name, value)` pairs of a [`ParsedCommand`].
+#[derive(Debug, Clone)]
+pub struct Iter<'a> {
+    args: std::slice::Iter<'a, ParsedArg>,
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = (&'a str, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.args
+            .next()
+            .map(|arg| (arg.name.as_ref(), arg.value.as_ref()))
+    }
+}
+
+/// Owning iterator over the `(name, value)` pairs of a [`ParsedCommand`].
+#[derive(Debug)]
+pub struct IntoIter {
+    args: std::vec::IntoIter<ParsedArg>,
+}
+
+impl Iterator for IntoIter {
+    type Item = (String, String);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.args.next().map(|arg| (arg.name, arg.value))
+    }
+}
+
+impl<'a> IntoIterator for &'a ParsedCommand {
+    type Item = (&'a str, &'a str);
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Iter {
+            args: self.args.iter(),
+        }
+    }
+}
+
+impl IntoIterator for ParsedCommand {
+    type Item = (String, String);
+    type IntoIter = IntoIter;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter {
+            args: self.args.into_iter(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Ambiguity, ArgInfo, Command, Error, ErrorFormatter, Policy, Priority, Value};
+    use proptest::prelude::*;
+
+    #[test]
     fn smoke_test() {
         let matches = Command::new("/hello")
             .flag("-foo")
             .arg("one", true)
-            .arg("two", true)
-            .arg("three", true)
-            .arg("four", true)
-            .parse("/hello -foo -spam bar -foo baz")
+            .arg("two", true)
+            .arg("three", true)
+            .arg("four", true)
+            .parse("/hello -foo -spam bar -foo baz")
+            .unwrap();
+        assert!(matches.has_flag("-foo"));
+        assert!(!matches.has_flag("-spam"));
+
+        assert_eq!(matches.args(), &["-spam", "bar", "-foo", "baz"]);
+    }
+
+    #[test]
+    fn whitespace() {
+        let matches = Command::new("/hello")
+            .arg("one", true)
+            .arg("two", true)
+            .parse("/hello   foo  bar");
+        assert!(matches.is_ok());
+        let matches = matches.unwrap();
+        assert_eq!(matches.arg("one").unwrap(), "foo");
+        assert_eq!(matches.arg("two").unwrap(), "bar");
+        assert_eq!(matches.rest("/hello   foo  bar"), "   foo  bar")
+    }
+
+    #[test]
+    fn required_arg() {
+        let matches = Command::new("/hello")
+            .arg("one", true)
+            .arg("two", true)
+            .parse("/hello foo");
+        assert!(matches.is_err());
+        assert_eq!(
+            matches.unwrap_err().to_string(),
+            "Missing required arg \"two\""
+        )
+    }
+
+    #[test]
+    fn optional_arg() {
+        let matches = Command::new("/hello")
+            .arg("one", false)
+            .arg("two", false)
+            .parse("/hello foo");
+        assert!(matches.is_ok());
+        assert_eq!(matches.unwrap().args(), &["foo"])
+    }
+
+    #[test]
+    fn multiple_flags() {
+        let matches = Command::new("/hello")
+            .flags(&["-foo", "-spam"])
+            .arg("one", true)
+            .arg("two", true)
+            .arg("three", true)
+            .parse("/hello -foo -spam bar -foo baz")
+            .unwrap();
+        assert!(matches.has_flag("-foo"));
+        assert!(matches.has_flag("-spam"));
+
+        assert_eq!(matches.args(), &["bar", "-foo", "baz"]);
+    }
+
+    #[test]
+    fn args() {
+        let matches = Command::new("/hello")
+            .flags(&["-foo", "-spam"])
+            .arg("one", true)
+            .arg("two", true)
+            .arg("three", true)
+            .parse("/hello -foo -spam bar -foo baz")
+            .unwrap();
+        assert!(matches.has_flag("-foo"));
+        assert!(matches.has_flag("-spam"));
+
+        assert_eq!(matches.args(), &["bar", "-foo", "baz"]);
+    }
+
+    #[test]
+    fn subcommand() {
+        let matches = Command::new("/hello")
+            .subcommand(
+                Command::new("subcommand")
+                    .flags(&["-foo", "-spam"])
+                    .arg("one", true)
+                    .arg("two", true)
+                    .arg("three", true),
+            )
+            .parse("/hello subcommand -foo -spam bar -foo baz")
+            .unwrap();
+
+        assert!(matches.subcommand().is_some());
+        assert_eq!(matches.subcommand().unwrap().0, "subcommand");
+        assert!(matches.subcommand().unwrap().1.has_flag("-foo"));
+        assert!(matches.subcommand().unwrap().1.has_flag("-spam"));
+        assert_eq!(
+            matches.subcommand().unwrap().1.args(),
+            &["bar", "-foo", "baz"]
+        );
+    }
+
+    #[test]
+    fn subcommand2() {
+        let matches = Command::new("/discord")
+            .subcommand(
+                Command::new("subcommand")
+                    .flags(&["-foo", "-spam"])
+                    .arg("one", true)
+                    .arg("two", true)
+                    .arg("three", true),
+            )
+            .parse("/discord subcommand -foo -spam bar -foo baz")
+            .unwrap();
+
+        assert!(matches.subcommand().is_some());
+        assert_eq!(matches.subcommand().unwrap().0, "subcommand");
+        assert!(matches.subcommand().unwrap().1.has_flag("-foo"));
+        assert!(matches.subcommand().unwrap().1.has_flag("-spam"));
+        assert_eq!(
+            matches.subcommand().unwrap().1.args(),
+            &["bar", "-foo", "baz"]
+        );
+    }
+
+    #[test]
+    fn opaque_subcommand() {
+        let matches = Command::new("/exec")
+            .flag("-foo")
+            .opaque_subcommand("run")
+            .parse("/exec run ls -la -foo /tmp")
+            .unwrap();
+
+        let (name, subcommand) = matches.subcommand().unwrap();
+        assert_eq!(name, "run");
+        assert_eq!(subcommand.raw_tokens(), &["ls", "-la", "-foo", "/tmp"]);
+        assert!(!subcommand.has_flag("-foo"));
+        assert!(subcommand.args().is_empty());
+    }
+
+    #[test]
+    fn arg_as_bool() {
+        let command = Command::new("/set").arg("option", true).arg("value", true);
+
+        for &value in &["true", "yes", "on", "1", "TRUE", "Yes", "On"] {
+            let matches = command.parse(&format!("/set option {}", value)).unwrap();
+            assert_eq!(matches.arg_as_bool("value"), Some(true), "{}", value);
+        }
+
+        for &value in &["false", "no", "off", "0", "FALSE", "No", "Off"] {
+            let matches = command.parse(&format!("/set option {}", value)).unwrap();
+            assert_eq!(matches.arg_as_bool("value"), Some(false), "{}", value);
+        }
+
+        let matches = command.parse("/set option maybe").unwrap();
+        assert_eq!(matches.arg_as_bool("value"), None);
+        assert_eq!(matches.arg_as_bool("missing"), None);
+    }
+
+    #[test]
+    fn value_flags() {
+        let matches = Command::new("/notify")
+            .value_flag("--user")
+            .flag("-quiet")
+            .arg("message", true)
+            .parse("/notify --user alice -quiet --user bob hello")
+            .unwrap();
+
+        assert!(matches.has_flag("--user"));
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.flag_values("--user"), &["alice", "bob"]);
+        assert_eq!(matches.flag_value("--user"), Some("bob"));
+        assert_eq!(matches.flag_value("-quiet"), None);
+        assert_eq!(matches.args(), &["hello"]);
+    }
+
+    #[test]
+    fn value_flag_missing_value() {
+        let matches = Command::new("/notify")
+            .value_flag("--user")
+            .parse("/notify --user");
+        assert_eq!(
+            matches.unwrap_err().to_string(),
+            "Missing value for flag \"--user\""
+        );
+    }
+
+    #[test]
+    fn clear_template() {
+        let template = Command::new("/hello")
+            .flags(&["-foo", "-spam"])
+            .value_flag("--user")
+            .arg("one", true)
+            .arg("two", true);
+
+        let matches = template
+            .clone()
+            .clear_flags()
+            .clear_args()
+            .arg("only", true)
+            .parse("/hello -foo")
+            .unwrap();
+        assert!(!matches.has_flag("-foo"));
+        assert_eq!(matches.arg("only"), Some("-foo"));
+
+        assert!(template
+            .parse("/hello -foo bar baz")
+            .unwrap()
+            .has_flag("-foo"));
+
+        let matches = Command::new("/x")
+            .int_value_flag("-n", 1..=3)
+            .clear_flags()
+            .value_flag("-n")
+            .parse("/x -n 9")
+            .unwrap();
+        assert_eq!(matches.flag_value("-n"), Some("9"));
+
+        let command = Command::new("/x")
+            .default_on_flag("--color")
+            .flags(&["-a", "-b"])
+            .require_one_of(&["-a", "-b"])
+            .clear_flags()
+            .flag("--color");
+        assert!(!command.parse("/x").unwrap().bool_flag("--color"));
+        assert!(command.parse("/x --color").unwrap().bool_flag("--color"));
+        assert_eq!(command.validate(), Ok(()));
+
+        let matches = Command::new("/x")
+            .shared_arg("s", true)
+            .clear_args()
+            .parse("/x")
+            .unwrap();
+        assert_eq!(matches.arg("s"), None);
+    }
+
+    #[test]
+    fn value_flag_equals() {
+        let matches = Command::new("/notify")
+            .value_flag("--user")
+            .arg("message", false)
+            .parse("/notify --user=alice --user= --other=x")
+            .unwrap();
+
+        assert_eq!(matches.flag_values("--user"), &["alice", ""]);
+        assert_eq!(matches.args(), &["--other=x"]);
+    }
+
+    #[test]
+    fn name_with_equals() {
+        let matches = Command::new("key")
+            .value_flag("key")
+            .arg("one", false)
+            .parse("key=value")
+            .unwrap();
+
+        assert_eq!(matches.flag_value("key"), Some("value"));
+
+        let matches = Command::new("key")
+            .arg("one", true)
+            .parse("key=value")
+            .unwrap();
+
+        assert_eq!(matches.arg("one"), Some("key=value"));
+    }
+
+    #[test]
+    fn error_formatter() {
+        struct German;
+
+        impl ErrorFormatter for German {
+            fn format(&self, error: &Error) -> String {
+                match error {
+                    Error::RequiredArgMissing(arg) => format!("Fehlendes Argument \"{}\"", arg),
+                    error => error.to_string(),
+                }
+            }
+        }
+
+        let error = Command::new("/hello")
+            .arg("one", true)
+            .parse("/hello")
+            .unwrap_err();
+
+        assert_eq!(error.format_with(&German), "Fehlendes Argument \"one\"");
+        assert_eq!(error.format_with(&|_: &Error| "nope".to_owned()), "nope");
+        assert_eq!(error.to_string(), "Missing required arg \"one\"");
+    }
+
+    fn round_trip_command() -> Command {
+        Command::new("/hello")
+            .allow_quotes(true)
+            .flags(&["-foo", "-spam"])
+            .value_flag("--user")
+            .multi_value_flag("--to")
+            .arg("one", false)
+            .arg("two", false)
+            .arg("three", false)
+    }
+
+    #[test]
+    fn to_command_line() {
+        let command = round_trip_command();
+        let matches = command
+            .parse(r#"/hello -spam --user "a b" -foo "-foo" '' c=d"#)
+            .unwrap();
+
+        let line = matches.to_command_line();
+        assert_eq!(line, r#"/hello -foo -spam --user "a b" "-foo" "" "c=d""#);
+        assert_eq!(command.parse(&line).unwrap(), matches);
+
+        let matches = command
+            .parse("/hello --to a --to b --user c --to d -- e f")
+            .unwrap();
+        let line = matches.to_command_line();
+        assert_eq!(line, "/hello --to a b --user c --to d -- e f");
+        assert_eq!(command.parse(&line).unwrap(), matches);
+
+        let topic = Command::new("/t")
+            .allow_quotes(true)
+            .flag("-q")
+            .arg_until_flag("text", true)
+            .arg("channel", false);
+        let matches = topic.parse("/t a b -q c").unwrap();
+        let line = matches.to_command_line();
+        assert_eq!(line, r#"/t "a b" -q c"#);
+        assert_eq!(topic.parse(&line).unwrap(), matches);
+        assert_eq!(
+            topic.parse("/t a b -q").unwrap().to_command_line(),
+            r#"/t -q "a b""#
+        );
+
+        let subcommand = Command::new("/discord")
+            .allow_quotes(true)
+            .subcommand(round_trip_command().arg("four", false));
+        let matches = subcommand.parse(r#"/discord /hello -foo "x y" z"#).unwrap();
+        assert_eq!(
+            subcommand.parse(&matches.to_command_line()).unwrap(),
+            matches
+        );
+        let matches = subcommand.parse("/discord /hello --to x y -- z").unwrap();
+        assert_eq!(
+            subcommand.parse(&matches.to_command_line()).unwrap(),
+            matches
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn to_command_line_round_trip(
+            tokens in proptest::collection::vec(
+                prop_oneof![
+                    Just("-foo".to_owned()),
+                    Just("-spam".to_owned()),
+                    Just("--user".to_owned()),
+                    Just("--to".to_owned()),
+                    Just("--".to_owned()),
+                    "[a-z=-]{1,4}",
+                    "\"[a-z '=\\\\-]{0,4}\"",
+                    "'[a-z \"=-]{0,4}'",
+                ],
+                0..8,
+            )
+        ) {
+            let input = format!("/hello {}", tokens.join(" "));
+            let greedy = Command::new("/hello")
+                .allow_quotes(true)
+                .flags(&["-foo", "-spam"])
+                .value_flag("--user")
+                .arg_until_flag("one", false)
+                .arg("two", false);
+
+            for command in &[round_trip_command(), greedy] {
+                if let Ok(matches) = command.parse(&input) {
+                    let line = matches.to_command_line();
+                    prop_assert_eq!(command.parse(&line).unwrap(), matches, "{}", line);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn parse_args_only() {
+        let command = Command::new("/echo").arg("text", true);
+
+        let matches = command.parse_args_only(["/echo"].iter().copied()).unwrap();
+        assert_eq!(matches.arg("text"), Some("/echo"));
+
+        let matches = command
+            .parse_from(["/echo", "/echo"].iter().copied())
+            .unwrap();
+        assert_eq!(matches.arg("text"), Some("/echo"));
+    }
+
+    #[test]
+    fn subcommand_arg_named_like_subcommand() {
+        let matches = Command::new("/hello")
+            .subcommand(Command::new("say").arg("text", true))
+            .parse("/hello say say")
+            .unwrap();
+
+        assert_eq!(matches.subcommand().unwrap().1.arg("text"), Some("say"));
+    }
+
+    #[test]
+    fn max_args() {
+        let command = Command::new("/nick").arg("name", true).max_args(1);
+
+        assert_eq!(command.parse("/nick bob").unwrap().arg("name"), Some("bob"));
+        assert_eq!(
+            command.parse("/nick bob alice").unwrap_err().to_string(),
+            "Expected at most 1 args, got 2"
+        );
+
+        let matches = Command::new("/hello")
+            .max_args(0)
+            .subcommand(Command::new("sub").arg("one", true))
+            .parse("/hello sub x")
+            .unwrap();
+        assert_eq!(matches.subcommand().unwrap().1.arg("one"), Some("x"));
+
+        let matches = Command::new("/say")
+            .rest_arg("message", true)
+            .max_args(1)
+            .parse("/say hello there")
+            .unwrap();
+        assert_eq!(matches.arg("message"), Some("hello there"));
+    }
+
+    #[test]
+    fn rest_arg() {
+        let command = Command::new("/kick")
+            .arg("user", true)
+            .rest_arg("reason", false);
+
+        let matches = command.parse("/kick bob  being   rude").unwrap();
+        assert_eq!(matches.arg("user"), Some("bob"));
+        assert_eq!(matches.arg("reason"), Some("being rude"));
+
+        let matches = command.parse("/kick bob").unwrap();
+        assert_eq!(matches.arg("reason"), None);
+
+        assert!(Command::new("/say")
+            .rest_arg("message", true)
+            .parse("/say")
+            .is_err());
+    }
+
+    #[test]
+    fn rest_arg_not_last() {
+        let command = Command::new("/kick")
+            .rest_arg("reason", false)
+            .arg("user", true);
+        assert_eq!(
+            command.validate(),
+            Err(crate::BuildError::RestArgNotLast("reason".into()))
+        );
+    }
+
+    #[test]
+    fn into_iter() {
+        let matches = Command::new("/hello")
+            .flag("-foo")
+            .arg("one", true)
+            .arg("two", false)
+            .arg("three", false)
+            .parse("/hello -foo bar baz")
+            .unwrap();
+
+        assert_eq!(
+            matches.iter().collect::<Vec<_>>(),
+            &[("one", "bar"), ("two", "baz")]
+        );
+
+        let map: std::collections::HashMap<String, String> = matches.into_iter().collect();
+        assert_eq!(map.len(), 2);
+        assert_eq!(map["one"], "bar");
+        assert_eq!(map["two"], "baz");
+    }
+
+    #[test]
+    fn check_ambiguities() {
+        let command = Command::new("/discord")
+            .flags(&["-v", "-verbose", "-quiet"])
+            .subcommand(Command::new("connect"))
+            .subcommand(Command::new("conn"))
+            .subcommand(
+                Command::new("server")
+                    .subcommand(Command::new("add"))
+                    .subcommand(Command::new("add")),
+            );
+
+        assert_eq!(
+            command.check_ambiguities(),
+            &[
+                Ambiguity::SubcommandPrefix {
+                    command: "/discord".into(),
+                    prefix: "conn".into(),
+                    name: "connect".into(),
+                },
+                Ambiguity::FlagPrefix {
+                    command: "/discord".into(),
+                    prefix: "-v".into(),
+                    flag: "-verbose".into(),
+                },
+                Ambiguity::DuplicateSubcommand {
+                    command: "server".into(),
+                    name: "add".into(),
+                },
+            ]
+        );
+
+        assert!(Command::new("/hello")
+            .flags(&["-foo", "-bar"])
+            .subcommand(Command::new("one"))
+            .check_ambiguities()
+            .is_empty());
+
+        let command = Command::new("/discord")
+            .flag("-e")
+            .rest_after_flag("-exec")
+            .subcommand(Command::new("connect").alias("co"))
+            .subcommand(Command::new("config"))
+            .subcommand(Command::new("join").alias("jn"))
+            .subcommand(Command::new("part").alias("jn"));
+        assert_eq!(
+            command.check_ambiguities(),
+            &[
+                Ambiguity::SubcommandPrefix {
+                    command: "/discord".into(),
+                    prefix: "co".into(),
+                    name: "config".into(),
+                },
+                Ambiguity::DuplicateSubcommand {
+                    command: "/discord".into(),
+                    name: "jn".into(),
+                },
+                Ambiguity::FlagPrefix {
+                    command: "/discord".into(),
+                    prefix: "-e".into(),
+                    flag: "-exec".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn shared_arg() {
+        let command = Command::new("/discord")
+            .allow_quotes(true)
+            .shared_arg("server", true)
+            .subcommand(
+                Command::new("channel").subcommand(Command::new("join").arg("channel", true)),
+            );
+
+        let matches = command.parse("/discord libera channel join #rust").unwrap();
+        assert_eq!(matches.arg("server"), Some("libera"));
+
+        let (_, channel) = matches.subcommand().unwrap();
+        assert_eq!(channel.arg("server"), Some("libera"));
+
+        let (_, join) = channel.subcommand().unwrap();
+        assert_eq!(join.arg("server"), Some("libera"));
+        assert_eq!(join.arg("channel"), Some("#rust"));
+
+        assert_eq!(command.parse(&matches.to_command_line()).unwrap(), matches);
+        assert!(command.parse("/discord").is_err());
+    }
+
+    #[test]
+    fn optional_shared_arg() {
+        let command = Command::new("/discord")
+            .shared_arg("server", false)
+            .subcommand(Command::new("join").arg("channel", true));
+
+        let matches = command.parse("/discord join #rust").unwrap();
+        assert_eq!(matches.arg("server"), None);
+        assert_eq!(
+            matches.subcommand().unwrap().1.arg("channel"),
+            Some("#rust")
+        );
+    }
+
+    #[test]
+    fn separator() {
+        let matches = Command::new("/hello")
+            .flags(&["-foo", "-spam"])
+            .arg("one", true)
+            .arg("two", false)
+            .parse("/hello -foo -- -spam bar")
+            .unwrap();
+
+        assert!(matches.has_flag("-foo"));
+        assert!(!matches.has_flag("-spam"));
+        assert_eq!(matches.args(), &["-spam", "bar"]);
+    }
+
+    #[test]
+    fn flag_detector() {
+        let command = Command::new("/mode")
+            .flag_detector(|token| token.starts_with('+') || token.starts_with('-'))
+            .value_flag("-k")
+            .flag("ignored")
+            .arg("target", true)
+            .arg("extra", false);
+
+        let matches = command.parse("/mode +o -v -k secret #rust").unwrap();
+        assert!(matches.has_flag("+o"));
+        assert!(matches.has_flag("-v"));
+        assert_eq!(matches.flag_value("-k"), Some("secret"));
+        assert_eq!(matches.arg("target"), Some("#rust"));
+
+        let matches = command.parse("/mode ignored -- +o").unwrap();
+        assert!(!matches.has_flag("ignored"));
+        assert_eq!(matches.args(), &["ignored", "--"]);
+
+        let matches = command.parse("/mode +o -- -v").unwrap();
+        assert_eq!(matches.args(), &["-v"]);
+    }
+
+    #[test]
+    fn parse_prefix() {
+        let command = Command::new("/msg")
+            .flag("-quiet")
+            .arg("target", true)
+            .arg("message", true);
+
+        let tokens = "/msg -quiet bob hi /msg alice hello there".split(' ');
+        let (first, rest) = command.parse_prefix(tokens).unwrap();
+        assert!(first.has_flag("-quiet"));
+        assert_eq!(first.args(), &["bob", "hi"]);
+        assert_eq!(rest, &["/msg", "alice", "hello", "there"]);
+
+        let (second, rest) = command.parse_prefix(rest.into_iter()).unwrap();
+        assert_eq!(second.args(), &["alice", "hello"]);
+        assert_eq!(rest, &["there"]);
+
+        let command = Command::new("/discord")
+            .subcommand(Command::new("join").arg("channel", true))
+            .subcommand(Command::new("say").rest_arg("text", true));
+        let (matches, rest) = command
+            .parse_prefix("/discord join #rust next".split(' '))
+            .unwrap();
+        assert_eq!(
+            matches.subcommand().unwrap().1.arg("channel"),
+            Some("#rust")
+        );
+        assert_eq!(rest, &["next"]);
+
+        let (_, rest) = command
+            .parse_prefix("/discord say a b c".split(' '))
+            .unwrap();
+        assert!(rest.is_empty());
+    }
+
+    #[test]
+    fn arg_present_count() {
+        let command = Command::new("/cmd")
+            .allow_quotes(true)
+            .arg("one", false)
+            .arg("two", false);
+
+        assert_eq!(command.parse("/cmd").unwrap().arg_present_count(), 0);
+        assert_eq!(command.parse(r#"/cmd """#).unwrap().arg_present_count(), 1);
+        assert_eq!(command.parse("/cmd a b c").unwrap().arg_present_count(), 2);
+
+        let matches = Command::new("/discord")
+            .shared_arg("server", true)
+            .subcommand(Command::new("join").arg("channel", false))
+            .parse("/discord libera join")
+            .unwrap();
+        assert_eq!(matches.subcommand().unwrap().1.arg_present_count(), 0);
+
+        let command = Command::new("/join")
+            .arg("channel", true)
+            .arg("key", false)
+            .arg_default("key", "none");
+        let matches = command.parse("/join #rust").unwrap();
+        assert_eq!(matches.arg("key"), Some("none"));
+        assert_eq!(matches.arg_present_count(), 1);
+        assert_eq!(
+            command.parse("/join #rust x").unwrap().arg_present_count(),
+            2
+        );
+    }
+
+    #[test]
+    fn rest_after_flag() {
+        let command = Command::new("/exec")
+            .flag("-bg")
+            .rest_after_flag("--cmd")
+            .arg("name", false);
+
+        let matches = command.parse("/exec -bg --cmd ls -la -- /tmp").unwrap();
+        assert!(matches.has_flag("-bg"));
+        assert_eq!(matches.flag_value("--cmd"), Some("ls -la -- /tmp"));
+        assert!(matches.args().is_empty());
+
+        let matches = command.parse("/exec -- --cmd").unwrap();
+        assert!(!matches.has_flag("--cmd"));
+        assert_eq!(matches.arg("name"), Some("--cmd"));
+
+        assert!(command.parse("/exec --cmd").is_err());
+    }
+
+    #[test]
+    fn require_one_of() {
+        let command = Command::new("/send")
+            .flags(&["-user", "-channel", "-quiet"])
+            .require_one_of(&["-user", "-channel"]);
+
+        assert_eq!(
+            command.parse("/send -quiet").unwrap_err().to_string(),
+            "Expected one of -user, -channel"
+        );
+        assert!(command
+            .parse("/send -channel")
+            .unwrap()
+            .has_flag("-channel"));
+        assert!(command.parse("/send -user -channel").is_ok());
+
+        let command = command.exclusive_flags(&["-user", "-channel"]);
+        assert!(command.parse("/send -user").is_ok());
+        assert_eq!(
+            command
+                .parse("/send -channel -quiet -user")
+                .unwrap_err()
+                .to_string(),
+            "Flags -user, -channel can't be used together"
+        );
+        assert!(command.validate().is_ok());
+
+        assert_eq!(
+            Command::new("/send").require_one_of(&["-user"]).validate(),
+            Err(crate::BuildError::UnregisteredFlag("-user".into()))
+        );
+    }
+
+    #[test]
+    fn quoted_flag_values() {
+        let command = Command::new("/say")
+            .allow_quotes(true)
+            .value_flag("--message")
+            .arg("target", false);
+
+        let matches = command
+            .parse(r#"/say --message "hello world" bob"#)
+            .unwrap();
+        assert_eq!(matches.flag_value("--message"), Some("hello world"));
+        assert_eq!(matches.arg("target"), Some("bob"));
+
+        let matches = command.parse(r#"/say --message="hello world""#).unwrap();
+        assert_eq!(matches.flag_value("--message"), Some("hello world"));
+
+        let matches = command.parse(r#"/say --message="" bob"#).unwrap();
+        assert_eq!(matches.flag_value("--message"), Some(""));
+        assert_eq!(matches.arg("target"), Some("bob"));
+
+        let matches = command.parse(r#"/say --message '' "-bob""#).unwrap();
+        assert_eq!(matches.flag_value("--message"), Some(""));
+        assert_eq!(matches.arg("target"), Some("-bob"));
+    }
+
+    #[test]
+    fn deny_extra_args() {
+        let command = Command::new("/nick")
+            .flag("-all")
+            .arg("name", true)
+            .arg("server", false)
+            .deny_extra_args(true);
+
+        assert!(command.parse("/nick -all bob libera").is_ok());
+        assert_eq!(
+            command
+                .parse("/nick bob libera extra more")
+                .unwrap_err()
+                .to_string(),
+            "Unexpected arg \"extra\" at position 2"
+        );
+
+        let command = Command::new("/say")
+            .arg("target", true)
+            .rest_arg("text", false)
+            .deny_extra_args(true);
+        assert!(command.parse("/say bob a b c").is_ok());
+
+        let command = Command::new("/discord")
+            .deny_extra_args(true)
+            .subcommand(Command::new("join").arg("channel", true));
+        assert!(command.parse("/discord join #rust").is_ok());
+    }
+
+    #[test]
+    fn parse_into() {
+        let command = Command::new("/hello")
+            .flag("-foo")
+            .arg("one", true)
+            .arg("two", false);
+        let mut buf = crate::ParsedCommandBuf::new();
+        assert!(buf.get().is_none());
+
+        let matches = command
+            .parse_into(&["/hello", "-foo", "bar", "baz"], &mut buf)
+            .unwrap();
+        assert!(matches.has_flag("-foo"));
+        assert_eq!(matches.args(), &["bar", "baz"]);
+
+        let matches = command
+            .parse_into(&["/hello", "", "qux"], &mut buf)
+            .unwrap();
+        assert!(!matches.has_flag("-foo"));
+        assert_eq!(matches.args(), &["qux"]);
+
+        assert!(command.parse_into(&["/hello"], &mut buf).is_err());
+        assert_eq!(buf.get().unwrap().args(), &["qux"]);
+    }
+
+    #[test]
+    fn arg_strip_prefix() {
+        let command = Command::new("/join")
+            .arg("channel", true)
+            .arg("key", false)
+            .arg_strip_prefix("channel", "#");
+
+        assert_eq!(
+            command.parse("/join #rust").unwrap().arg("channel"),
+            Some("rust")
+        );
+        assert_eq!(
+            command.parse("/join rust").unwrap().arg("channel"),
+            Some("rust")
+        );
+        assert_eq!(
+            command.parse("/join ##rust").unwrap().arg("channel"),
+            Some("#rust")
+        );
+        assert_eq!(command.parse("/join a #b").unwrap().arg("key"), Some("#b"));
+    }
+
+    #[test]
+    fn arg_at() {
+        let command = Command::new("/connect")
+            .shared_arg("server", true)
+            .arg("host", true)
+            .arg("port", false);
+
+        assert_eq!(command.arg_name_at(0), Some("server"));
+        assert_eq!(command.arg_name_at(1), Some("host"));
+        assert_eq!(command.arg_name_at(2), Some("port"));
+        assert_eq!(command.arg_name_at(3), None);
+        assert_eq!(command.arg_index("server"), Some(0));
+        assert_eq!(command.arg_index("port"), Some(2));
+        assert_eq!(command.arg_index("nick"), None);
+
+        let matches = command.parse("/connect libera irc.libera.chat").unwrap();
+        assert_eq!(matches.arg_value_at(0), Some("libera"));
+        assert_eq!(matches.arg_value_at(1), Some("irc.libera.chat"));
+        assert_eq!(matches.arg_value_at(2), None);
+        assert_eq!(matches.arg_value_at(100), None);
+
+        let command = Command::new("/server")
+            .shared_arg("network", false)
+            .subcommand(Command::new("add").arg("host", true))
+            .arg("name", false)
+            .allow_mixed(true);
+        // The optional shared arg is skipped because a subcommand follows, and the mixed arg
+        // gets nothing because the subcommand takes every token.
+        let matches = command.parse("/server add irc.libera.chat").unwrap();
+        assert_eq!(matches.arg_value_at(0), None);
+        assert_eq!(matches.arg_value_at(1), None);
+        let (_, add) = matches.subcommand().unwrap();
+        assert_eq!(add.arg_value_at(0), Some("irc.libera.chat"));
+    }
+
+    #[test]
+    fn multi_word_name() {
+        let command = Command::new("/server add")
+            .arg("name", true)
+            .arg("host", false);
+
+        let matches = command
+            .parse("/server   add libera irc.libera.chat")
+            .unwrap();
+        assert_eq!(matches.command(), "/server add");
+        assert_eq!(matches.args(), &["libera", "irc.libera.chat"]);
+        assert_eq!(crate::ParseTree::from(&matches).span, Some(0..13));
+
+        let matches = command.parse("/server libera").unwrap();
+        assert_eq!(matches.args(), &["/server", "libera"]);
+
+        let matches = Command::new("/discord")
+            .subcommand(Command::new("server add").arg("name", true))
+            .parse("/discord server add libera")
+            .unwrap();
+        assert_eq!(matches.subcommand().unwrap().0, "server add");
+        assert_eq!(matches.subcommand().unwrap().1.arg("name"), Some("libera"));
+    }
+
+    #[test]
+    fn merge_defaults() {
+        let command = Command::new("/connect")
+            .arg("server", false)
+            .arg("port", false)
+            .arg("nick", false)
+            .arg_default("nick", "guest");
+        let profile = Command::new("/connect")
+            .arg("server", false)
+            .arg("port", false)
+            .arg_default("server", "libera")
+            .arg_default("port", "6697");
+        let global = Command::new("/connect")
+            .arg("port", false)
+            .arg("tls", false)
+            .arg_default("port", "6667")
+            .arg_default("tls", "on");
+
+        let mut matches = command.parse("/connect oftc").unwrap();
+        assert_eq!(matches.arg("nick"), Some("guest"));
+        assert_eq!(matches.arg("port"), None);
+
+        matches.merge_defaults(&profile);
+        matches.merge_defaults(&global);
+        assert_eq!(matches.arg("server"), Some("oftc"));
+        assert_eq!(matches.arg("port"), Some("6697"));
+        assert_eq!(matches.arg("nick"), Some("guest"));
+        assert_eq!(matches.arg("tls"), Some("on"));
+    }
+
+    #[test]
+    fn csv_value_flag() {
+        let command = Command::new("/set")
+            .csv_value_flag("--channels")
+            .allow_quotes(true);
+
+        let matches = command.parse("/set --channels rust").unwrap();
+        assert_eq!(matches.flag_csv("--channels"), Some(vec!["rust"]));
+
+        let matches = command.parse("/set --channels rust,weechat,irc").unwrap();
+        assert_eq!(
+            matches.flag_csv("--channels"),
+            Some(vec!["rust", "weechat", "irc"])
+        );
+
+        let matches = command.parse(r#"/set --channels=" rust, ,irc,""#).unwrap();
+        assert_eq!(matches.flag_csv("--channels"), Some(vec!["rust", "irc"]));
+        assert_eq!(command.parse("/set").unwrap().flag_csv("--channels"), None);
+    }
+
+    #[test]
+    fn root_alias() {
+        let command = Command::new("/msg")
+            .alias("/query")
+            .alias("/m")
+            .arg("nick", true);
+
+        for input in &["/msg alice", "/query alice", "/m alice", "alice"] {
+            let matches = command.parse(input).unwrap();
+            assert_eq!(matches.command(), "/msg");
+            assert_eq!(matches.args(), &["alice"]);
+        }
+
+        let matches = command.parse("/q alice").unwrap();
+        assert_eq!(matches.args(), &["/q"]);
+    }
+
+    #[test]
+    fn unknown_flags_as_args() {
+        let command = Command::new("/say").flag("-v").rest_arg("text", false);
+
+        let matches = command.parse("/say -unknown text").unwrap();
+        assert!(!matches.has_flag("-unknown"));
+        assert_eq!(matches.args(), &["-unknown text"]);
+
+        let matches = command
+            .clone()
+            .unknown_flags(Policy::AsArg)
+            .parse("/say -v --unknown -v")
+            .unwrap();
+        assert!(matches.has_flag("-v"));
+        assert_eq!(matches.args(), &["--unknown -v"]);
+    }
+
+    #[test]
+    fn unknown_flags_error() {
+        let command = Command::new("/say")
+            .flag("-v")
+            .arg("text", false)
+            .unknown_flags(Policy::Error);
+
+        assert!(matches!(
+            command.parse("/say -v -unknown"),
+            Err(Error::UnknownFlag(flag, _)) if flag == "-unknown"
+        ));
+        assert_eq!(command.parse("/say -").unwrap().args(), &["-"]);
+        assert_eq!(command.parse("/say -- -x").unwrap().args(), &["-x"]);
+        assert_eq!(command.parse("/say x -y").unwrap().args(), &["x"]);
+    }
+
+    #[test]
+    fn subcommand_error_path() {
+        let command = Command::new("/discord")
+            .subcommand(Command::new("server").subcommand(Command::new("add").arg("name", true)));
+
+        let error = command.parse("/discord server add").unwrap_err();
+        match &error {
+            Error::InSubcommand { path, source } => {
+                assert_eq!(path, &["server", "add"]);
+                assert!(matches!(**source, Error::RequiredArgMissing(ref arg) if arg == "name"));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert_eq!(
+            error.to_string(),
+            "Missing required arg \"name\" (in server add)"
+        );
+    }
+
+    #[test]
+    fn flags_before_subcommand() {
+        let command = Command::new("/discord")
+            .flag("--verbose")
+            .value_flag("--guild")
+            .subcommand(
+                Command::new("server")
+                    .subcommand(Command::new("add").flag("--force").arg("name", true)),
+            );
+
+        let matches = command
+            .parse("/discord --verbose --guild rust server add --force libera")
+            .unwrap();
+        assert!(matches.has_flag("--verbose"));
+        assert_eq!(matches.flag_value("--guild"), Some("rust"));
+
+        let (name, server) = matches.subcommand().unwrap();
+        assert_eq!(name, "server");
+        let (name, add) = server.subcommand().unwrap();
+        assert_eq!(name, "add");
+        assert!(add.has_flag("--force"));
+        assert!(!add.has_flag("--verbose"));
+        assert_eq!(add.arg("name"), Some("libera"));
+
+        let line = matches.to_command_line();
+        assert_eq!(
+            line,
+            "/discord --verbose --guild rust server add --force libera"
+        );
+        assert_eq!(command.parse(&line).unwrap(), matches);
+
+        let command = Command::new("/discord")
+            .flag("--verbose")
+            .shared_arg("server", true)
+            .subcommand(Command::new("join").arg("channel", true));
+        let matches = command.parse("/discord --verbose libera join x").unwrap();
+        assert!(matches.has_flag("--verbose"));
+        assert_eq!(matches.arg("server"), Some("libera"));
+        let (name, join) = matches.subcommand().unwrap();
+        assert_eq!(name, "join");
+        assert_eq!(join.arg("channel"), Some("x"));
+        assert_eq!(command.parse(&matches.to_command_line()).unwrap(), matches);
+    }
+
+    #[test]
+    fn flatten_subcommand() {
+        let command = Command::new("/buffer")
+            .value_flag("--name")
+            .subcommand(
+                Command::new("move")
+                    .flag("--force")
+                    .value_flag("--name")
+                    .arg("target", true),
+            )
+            .subcommand(Command::new("close").arg("target", true))
+            .flatten_subcommand("move");
+
+        let matches = command.parse("/buffer move --force 3").unwrap();
+        assert!(matches.has_flag("--force"));
+        assert_eq!(matches.arg("target"), Some("3"));
+        assert_eq!(matches.subcommand().unwrap().1.arg("target"), Some("3"));
+
+        let matches = command.parse("/buffer --name a move --name b 3").unwrap();
+        assert_eq!(matches.flag_value("--name"), Some("a"));
+        assert_eq!(
+            matches.subcommand().unwrap().1.flag_value("--name"),
+            Some("b")
+        );
+
+        let matches = command.parse("/buffer close 3").unwrap();
+        assert_eq!(matches.arg("target"), None);
+    }
+
+    #[test]
+    fn named_args() {
+        let command = Command::new("/discord")
+            .shared_arg("server", true)
+            .subcommand(Command::new("join").arg("channel", true));
+
+        let matches = command.parse("/discord libera join #rust").unwrap();
+        let (_, join) = matches.subcommand().unwrap();
+        assert_eq!(
+            join.named_args(),
+            &[("channel", "#rust"), ("server", "libera")]
+        );
+        assert_eq!(join.clone().into_args(), &["#rust", "libera"]);
+        assert_eq!(
+            join.into_named_args(),
+            &[
+                ("channel".to_owned(), "#rust".to_owned()),
+                ("server".to_owned(), "libera".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn flag_prefixes() {
+        let command = Command::new("/set").flag("verbose").value_flag("--name");
+        assert_eq!(command.validate(), Ok(()));
+        assert_eq!(
+            command.clone().flag_prefixes(&["-"]).validate(),
+            Err(crate::BuildError::FlagMissingPrefix("verbose".into()))
+        );
+
+        let command = command
+            .clear_flags()
+            .flag("+v")
+            .value_flag("--name")
+            .flag_prefixes(&["-", "+"]);
+        assert_eq!(command.validate(), Ok(()));
+    }
+
+    #[test]
+    fn deferred_name() {
+        let command = Command::default().arg("nick", true);
+        assert_eq!(command.validate(), Err(crate::BuildError::EmptyName));
+        assert_eq!(command.parse("/nick alice").unwrap().args(), &["/nick"]);
+
+        let command = command.set_name("/nick");
+        assert_eq!(command.get_name(), "/nick");
+        assert_eq!(command.validate(), Ok(()));
+
+        let matches = command.parse("/nick alice").unwrap();
+        assert_eq!(matches.command(), "/nick");
+        assert_eq!(matches.args(), &["alice"]);
+
+        let command = Command::new("/buffer").subcommand(Command::default());
+        assert_eq!(command.validate(), Err(crate::BuildError::EmptyName));
+    }
+
+    #[test]
+    fn max_subcommand_depth() {
+        let command = Command::new("/a")
+            .subcommand(
+                Command::new("b").subcommand(Command::new("c").subcommand(Command::new("d"))),
+            )
+            .max_subcommand_depth(2);
+
+        assert!(command.parse("/a b c").is_ok());
+        match command.parse("/a b c d").unwrap_err() {
+            Error::InSubcommand { path, source } => {
+                assert_eq!(path, &["b", "c"]);
+                assert!(matches!(*source, Error::MaxDepthExceeded));
+            }
+            other => panic!("unexpected error {:?}", other),
+        }
+        assert!(command.max_subcommand_depth(0).parse("/a b").is_err());
+    }
+
+    #[test]
+    fn behaves_like() {
+        let old = Command::new("/msg")
+            .flag("-n")
+            .arg("nick", true)
+            .rest_arg("text", false);
+        let new: Command = "/msg -n <nick> [text*]".parse().unwrap();
+        let inputs = ["/msg alice hi there", "/msg -n bob", "/msg", "alice -n"];
+        assert!(old.behaves_like(&new, &inputs));
+        assert!(new.behaves_like(&old, &inputs));
+
+        let changed = old.clone().arg_default("text", "hello");
+        assert!(changed.behaves_like(&old, &inputs[..1]));
+        assert!(!changed.behaves_like(&old, &inputs));
+        assert!(!old.behaves_like(&Command::new("/msg").arg("nick", false), &["/msg"]));
+    }
+
+    #[test]
+    fn arg_choices() {
+        let command = Command::new("/status")
+            .arg("status", true)
+            .arg_choices("status", &["online", "idle", "dnd"]);
+
+        assert_eq!(
+            command.parse("/status idle").unwrap().arg("status"),
+            Some("idle")
+        );
+        let error = command.parse("/status away").unwrap_err();
+        assert!(
+            matches!(&error, Error::InvalidChoice { arg, value, .. } if arg == "status" && value == "away")
+        );
+        assert_eq!(
+            error.to_string(),
+            "Invalid value \"away\" for \"status\", expected one of online, idle, dnd"
+        );
+    }
+
+    #[test]
+    fn problems() {
+        use crate::BuildError;
+
+        let command = Command::new("/connect")
+            .flag("-tls")
+            .flag_group("Connection", &["-tls", "-port", "-ipv6"])
+            .arg("server", true)
+            .rest_arg("rest", false)
+            .arg("server", false)
+            .subcommand(Command::new("add"))
+            .subcommand(Command::new("add").exclusive_flags(&["-a", "-b"]));
+
+        assert_eq!(
+            command.problems(),
+            &[
+                BuildError::UnregisteredGroupFlag {
+                    group: "Connection".into(),
+                    flag: "-port".into(),
+                },
+                BuildError::UnregisteredGroupFlag {
+                    group: "Connection".into(),
+                    flag: "-ipv6".into(),
+                },
+                BuildError::DuplicateArg("server".into()),
+                BuildError::RestArgNotLast("rest".into()),
+                BuildError::SubcommandAndArgsConflict("/connect".into()),
+                BuildError::DuplicateSubcommand("add".into()),
+                BuildError::UnregisteredFlag("-a".into()),
+                BuildError::UnregisteredFlag("-b".into()),
+            ]
+        );
+        assert_eq!(command.validate(), Err(command.problems().remove(0)));
+        assert!(Command::new("/ok").arg("a", true).problems().is_empty());
+    }
+
+    #[test]
+    fn mixed_args_and_subcommands() {
+        use crate::BuildError;
+
+        let command = Command::new("/query")
+            .shared_arg("network", false)
+            .subcommand(Command::new("help"));
+        assert_eq!(command.validate(), Ok(()));
+
+        let command = command.arg("nick", true);
+        assert_eq!(
+            command.validate(),
+            Err(BuildError::SubcommandAndArgsConflict("/query".into()))
+        );
+        assert_eq!(command.clone().allow_mixed(true).validate(), Ok(()));
+        assert_eq!(
+            command.root_args_when_no_subcommand(true).validate(),
+            Ok(())
+        );
+    }
+
+    #[test]
+    fn parse_with_preset() {
+        let command = Command::new("/connect")
+            .flags(&["-tls", "-plain", "-quiet"])
+            .exclusive_flags(&["-tls", "-plain"])
+            .require_one_of(&["-tls", "-plain"])
+            .arg("server", true);
+
+        let matches = command
+            .parse_with_preset("/connect -quiet libera", &["-tls"])
+            .unwrap();
+        assert!(matches.has_flag("-tls"));
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.arg("server"), Some("libera"));
+        assert!(command.parse("/connect libera").is_err());
+
+        assert!(matches!(
+            command.parse_with_preset("/connect -plain libera", &["-tls"]),
+            Err(Error::ConflictingFlags(_))
+        ));
+    }
+
+    #[test]
+    fn is_empty() {
+        let command = Command::new("/buffer")
+            .flag("-all")
+            .arg("name", false)
+            .arg_default("name", "core")
+            .shared_arg("server", false)
+            .subcommand(Command::new("close"));
+
+        assert!(command.parse("/buffer").unwrap().is_empty());
+        assert!(!command.parse("/buffer -all").unwrap().is_empty());
+        assert!(!command.parse("/buffer libera").unwrap().is_empty());
+
+        let matches = command.parse("/buffer libera close").unwrap();
+        assert!(!matches.is_empty());
+        assert!(matches.subcommand().unwrap().1.is_empty());
+    }
+
+    #[test]
+    fn parse_many() {
+        let command = Command::new("/say")
+            .allow_quotes(true)
+            .rest_arg("text", false);
+
+        let matches = command.parse_many(r#"/say "a; b"; /say 'c;d' e;"#).unwrap();
+        let texts: Vec<_> = matches.iter().map(|m| m.arg("text")).collect();
+        assert_eq!(texts, &[Some("a; b"), Some("c;d e")]);
+
+        assert!(matches!(
+            command.parse_many(r#"/say "a; /say b"#),
+            Err(Error::UnbalancedQuote)
+        ));
+    }
+
+    #[test]
+    fn arg_until_flag() {
+        let command = Command::new("/topic")
+            .flag("-quiet")
+            .value_flag("-buffer")
+            .arg_until_flag("topic", true)
+            .arg("extra", false);
+
+        let matches = command
+            .parse("/topic the new topic -quiet -buffer=core more")
+            .unwrap();
+        assert_eq!(matches.arg("topic"), Some("the new topic"));
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.flag_value("-buffer"), Some("core"));
+        assert_eq!(matches.arg("extra"), Some("more"));
+
+        let matches = command.parse("/topic -- a -quiet").unwrap();
+        assert_eq!(matches.arg("topic"), Some("a -quiet"));
+        assert!(!matches.has_flag("-quiet"));
+
+        assert!(matches!(
+            command.parse("/topic -quiet"),
+            Err(Error::RequiredArgMissing(_))
+        ));
+    }
+
+    #[test]
+    fn render_with_source() {
+        let command = Command::new("/say")
+            .allow_quotes(true)
+            .unknown_flags(Policy::Error)
+            .arg("channel", true)
+            .arg_choices("channel", &["#rust", "#weechat"])
+            .deny_extra_args(true);
+
+        let render = |input: &str| command.parse(input).unwrap_err().render_with_source(input);
+        assert_eq!(
+            render("/say -x hi"),
+            "Unknown flag \"-x\"\n/say -x hi\n     ^^"
+        );
+        assert_eq!(
+            render("/say #rust é"),
+            "Unexpected arg \"é\" at position 1\n/say #rust é\n           ^"
+        );
+        assert_eq!(
+            render("/say"),
+            "Missing required arg \"channel\"\n/say\n    ^"
+        );
+        assert_eq!(
+            render(r##"/say "#rust"##),
+            "Unbalanced quote\n/say \"#rust\n     ^^^^^^"
+        );
+        assert_eq!(
+            render("/say #rus #rust"),
+            "Invalid value \"#rus\" for \"channel\", expected one of #rust, #weechat\n/say #rus #rust\n     ^^^^"
+        );
+        assert_eq!(
+            render("/say #rust #rust"),
+            "Unexpected arg \"#rust\" at position 1\n/say #rust #rust\n           ^^^^^"
+        );
+
+        let error = Command::new("/a")
+            .exclusive_flags(&["-x", "-y"])
+            .flags(&["-x", "-y"])
+            .parse("/a -x -y")
+            .unwrap_err();
+        assert_eq!(error.render_with_source("/a -x -y"), error.to_string());
+    }
+
+    #[test]
+    fn args_as() {
+        let command = Command::new("/roll").arg("count", true).arg("sides", false);
+
+        let matches = command.parse("/roll 2 6").unwrap();
+        assert_eq!(matches.args_as::<u32>(), &[Ok(2), Ok(6)]);
+
+        let matches = command.parse("/roll 2 x").unwrap();
+        let values = matches.args_as::<u32>();
+        assert_eq!(values[0], Ok(2));
+        assert!(values[1].is_err());
+    }
+
+    #[test]
+    fn arg_range() {
+        let command = Command::new("/mode")
+            .flag("-quiet")
+            .arg("target", true)
+            .arg("modes", false)
+            .arg_range(2, Some(4));
+
+        assert!(command.parse("/mode -quiet #rust +o alice bob").is_ok());
+        assert!(matches!(
+            command.parse("/mode -quiet #rust"),
+            Err(Error::TooFewArgs { min: 2, got: 1 })
+        ));
+        assert!(matches!(
+            command.parse("/mode #rust +ooo a b c"),
+            Err(Error::TooManyArgs { max: 4, got: 5 })
+        ));
+
+        let command = Command::new("/kick")
+            .arg("user", true)
+            .rest_arg("reason", false)
+            .arg_range(1, None);
+        assert_eq!(
+            command
+                .parse("/kick bob go away now")
+                .unwrap()
+                .arg("reason"),
+            Some("go away now")
+        );
+
+        let command = Command::new("/mode")
+            .arg("target", true)
+            .arg("modes", true)
+            .rest_arg("params", false)
+            .arg_range(2, Some(5));
+        assert!(command.parse("/mode a b c d e").is_ok());
+        assert!(matches!(
+            command.parse("/mode a b c d e f"),
+            Err(Error::TooManyArgs { max: 5, got: 6 })
+        ));
+
+        let command = Command::new("/nick")
+            .arg("name", true)
+            .max_args(1)
+            .arg_range(1, None);
+        assert!(matches!(
+            command.parse("/nick bob alice"),
+            Err(Error::TooManyArgs { max: 1, got: 2 })
+        ));
+        assert!(matches!(
+            command.parse("/nick"),
+            Err(Error::TooFewArgs { min: 1, got: 0 })
+        ));
+    }
+
+    #[test]
+    fn name_as_arg() {
+        let command = Command::new("/echo").rest_arg("text", false);
+        let matches = command.parse("/echo echo").unwrap();
+        assert_eq!(matches.arg("text"), Some("echo"));
+        assert_eq!(matches.rest("/echo echo"), " echo");
+        assert_eq!(
+            command.parse("echo /echo").unwrap().rest("echo /echo"),
+            "echo /echo"
+        );
+
+        let matches = command.parse("/echo /echo /echo").unwrap();
+        assert_eq!(matches.arg("text"), Some("/echo /echo"));
+
+        let command = Command::new("echo").arg("text", true);
+        assert_eq!(
+            command.parse("echo echo").unwrap().arg("text"),
+            Some("echo")
+        );
+
+        let command = Command::new("/say").subcommand(Command::new("echo").arg("text", true));
+        let matches = command.parse("/say echo echo").unwrap();
+        assert_eq!(matches.subcommand().unwrap().1.arg("text"), Some("echo"));
+    }
+
+    #[test]
+    fn default_on_flag() {
+        let command = Command::new("/set")
+            .default_on_flag("--color")
+            .default_on_flag("-bell")
+            .flag("--quiet");
+
+        let matches = command.parse("/set").unwrap();
+        assert!(matches.bool_flag("--color"));
+        assert!(!matches.has_flag("--color"));
+        assert!(matches.bool_flag("-bell"));
+        assert!(!matches.bool_flag("--quiet"));
+
+        let matches = command.parse("/set --color -no-bell --quiet").unwrap();
+        assert!(matches.bool_flag("--color"));
+        assert!(!matches.bool_flag("-bell"));
+        assert!(matches.bool_flag("--quiet"));
+
+        let matches = command.parse("/set --color --no-color").unwrap();
+        assert!(!matches.bool_flag("--color"));
+        assert!(command
+            .parse_with_preset("/set", &["--no-color"])
+            .map(|matches| !matches.bool_flag("--color"))
+            .unwrap());
+    }
+
+    #[test]
+    fn remainder() {
+        let command = Command::new("/say")
+            .flag("-v")
+            .allow_quotes(true)
+            .rest_arg("text", false)
+            .subcommand(Command::new("me").flag("-q").rest_arg("action", false));
+
+        let matches = command.parse_borrowed("/say -v hello   world").unwrap();
+        assert_eq!(matches.remainder(), Some("hello   world"));
+        assert_eq!(matches.arg("text"), Some("hello world"));
+
+        let matches = command
+            .parse_borrowed(r#"/say me -q waves  "a  b""#)
+            .unwrap();
+        assert_eq!(matches.remainder(), Some(r#"waves  "a  b""#));
+
+        assert_eq!(
+            command.parse_borrowed("/say -v ").unwrap().remainder(),
+            None
+        );
+    }
+
+    #[test]
+    fn subcommand_mut() {
+        let mut command = Command::new("/buffer")
+            .subcommand(Command::new("close").alias("c"))
+            .subcommand(Command::new("move").arg("target", true));
+
+        assert!(command.subcommand_mut("open").is_none());
+
+        let close = command.subcommand_mut("c").unwrap();
+        *close = std::mem::take(close).flag("-all");
+
+        let matches = command.parse("/buffer close -all").unwrap();
+        assert!(matches.subcommand().unwrap().1.has_flag("-all"));
+        assert_eq!(command.subcommand_mut("move").unwrap().get_name(), "move");
+    }
+
+    #[test]
+    fn int_value_flag() {
+        let command = Command::new("/history").int_value_flag("-lines", 1..=100);
+
+        let matches = command.parse("/history -lines 50").unwrap();
+        assert_eq!(matches.flag_as::<i64>("-lines"), Some(50));
+        assert_eq!(
+            command.parse("/history").unwrap().flag_as::<i64>("-lines"),
+            None
+        );
+
+        let error = command.parse("/history -lines=500").unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Value of flag \"-lines\" must be between 1 and 100"
+        );
+
+        let error = command.parse("/history -lines ten").unwrap_err();
+        assert!(matches!(
+            error,
+            Error::InvalidFlagValue { ref flag, ref message, .. }
+                if flag == "-lines" && message == "must be a number"
+        ));
+    }
+
+    #[test]
+    fn parse_lossy() {
+        let command = Command::new("/ban")
+            .flags(&["-soft", "-hard"])
+            .exclusive_flags(&["-soft", "-hard"])
+            .value_flag("-reason")
+            .arg("nick", true)
+            .arg("channel", true)
+            .arg_choices("channel", &["#a", "#b"]);
+
+        let (matches, errors) = command.parse_lossy("/ban -soft -hard bob #c");
+        assert!(matches.has_flag("-soft"));
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        assert_eq!(matches.arg("channel"), Some("#c"));
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::ConflictingFlags(_), Error::InvalidChoice { .. }]
+        ));
+
+        let (matches, errors) = command.parse_lossy("/ban -reason");
+        assert!(matches.has_flag("-reason"));
+        assert!(matches!(
+            errors.as_slice(),
+            [
+                Error::FlagValueMissing(..),
+                Error::RequiredArgMissing(_),
+                Error::RequiredArgMissing(_)
+            ]
+        ));
+
+        let (matches, errors) = command.parse_lossy("/ban bob #a");
+        assert_eq!(matches, command.parse("/ban bob #a").unwrap());
+        assert!(errors.is_empty());
+    }
+
+    #[test]
+    fn shortest_prefix() {
+        use crate::shortest_prefix;
+
+        assert_eq!(shortest_prefix("connect", &["config", "join"]), "conn");
+        assert_eq!(shortest_prefix("join", &["joined"]), "join");
+        assert_eq!(shortest_prefix("joined", &["join"]), "joine");
+        assert_eq!(shortest_prefix("list", &[]), "l");
+        assert_eq!(shortest_prefix("list", &["list"]), "list");
+        assert_eq!(shortest_prefix("éa", &["éb"]), "éa");
+        assert_eq!(shortest_prefix("", &["a"]), "");
+    }
+
+    #[test]
+    fn subcommand_abbreviations() {
+        let command = Command::new("/discord")
+            .allow_subcommand_abbreviation(true)
+            .subcommand(Command::new("connect"))
+            .subcommand(Command::new("config").alias("settings"))
+            .subcommand(Command::new("join"))
+            .subcommand(Command::new("joined"))
+            .subcommand(Command::new("status"));
+
+        let abbreviations = command.subcommand_abbreviations();
+        assert_eq!(
+            abbreviations,
+            &[
+                ("connect", "conn"),
+                ("config", "conf"),
+                ("join", "join"),
+                ("joined", "joine"),
+                ("status", "st"),
+            ]
+        );
+        for (name, abbreviation) in abbreviations {
+            let input = format!("/discord {}", abbreviation);
+            assert_eq!(command.parse(&input).unwrap().subcommand().unwrap().0, name);
+        }
+    }
+
+    #[test]
+    fn hash_parsed_commands() {
+        use std::collections::HashSet;
+
+        let command = Command::new("/window")
+            .flags(&["-a", "-b", "-c", "-d"])
+            .subcommand(Command::new("close").arg("number", false));
+
+        let mut seen = HashSet::new();
+        assert!(seen.insert(command.parse("/window -a -b -c -d").unwrap()));
+        assert!(!seen.insert(command.parse("/window -d -c -b -a").unwrap()));
+        assert!(seen.insert(command.parse("/window close 2").unwrap()));
+        assert!(!seen.insert(command.parse("/window  close  2").unwrap()));
+        assert!(seen.insert(command.parse("/window close 3").unwrap()));
+        assert_eq!(seen.len(), 3);
+    }
+
+    #[test]
+    fn subcommand_abbreviation() {
+        let command = Command::new("/discord")
+            .allow_subcommand_abbreviation(true)
+            .subcommand(Command::new("connect").arg("token", false))
+            .subcommand(Command::new("config"))
+            .subcommand(Command::new("join"))
+            .subcommand(Command::new("joined"));
+
+        let matches = command.parse("/discord conn abc").unwrap();
+        assert_eq!(matches.subcommand().unwrap().0, "connect");
+        assert_eq!(matches.subcommand().unwrap().1.arg("token"), Some("abc"));
+        assert_eq!(
+            command.parse("/discord j").unwrap_err().to_string(),
+            "Ambiguous subcommand \"j\", could be join, joined"
+        );
+        assert!(matches!(
+            command.parse("/discord con"),
+            Err(Error::AmbiguousSubcommand { ref input, ref candidates, .. })
+                if input == "con" && candidates == &["connect", "config"]
+        ));
+        assert_eq!(
+            command
+                .parse("/discord join")
+                .unwrap()
+                .subcommand()
+                .unwrap()
+                .0,
+            "join"
+        );
+        assert_eq!(
+            command
+                .parse("/discord joine")
+                .unwrap()
+                .subcommand()
+                .unwrap()
+                .0,
+            "joined"
+        );
+
+        let exact = Command::new("/discord").subcommand(Command::new("connect"));
+        assert!(exact.parse("/discord conn").unwrap().subcommand().is_none());
+    }
+
+    #[test]
+    fn pre_tokenize() {
+        fn expand(tokens: Vec<String>) -> Vec<String> {
+            tokens
+                .into_iter()
+                .flat_map(|token| match token.as_str() {
+                    "gg" => vec!["good".to_owned(), "game".to_owned()],
+                    "-q" => vec!["-quiet".to_owned()],
+                    _ => vec![token],
+                })
+                .collect()
+        }
+
+        let command = Command::new("/say")
+            .flag("-quiet")
+            .rest_arg("text", true)
+            .allow_quotes(true)
+            .pre_tokenize(expand);
+
+        let matches = command.parse("/say -q gg all").unwrap();
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.arg("text"), Some("good game all"));
+
+        let matches = command.parse(r#"/say "-quiet""#).unwrap();
+        assert!(!matches.has_flag("-quiet"));
+        assert_eq!(matches.arg("text"), Some("-quiet"));
+    }
+
+    #[test]
+    fn root_args_when_no_subcommand() {
+        let command = Command::new("/query")
+            .arg("nick", true)
+            .rest_arg("message", false)
+            .subcommand(Command::new("help").arg("topic", false))
+            .root_args_when_no_subcommand(true);
+
+        let matches = command.parse("/query help options").unwrap();
+        assert_eq!(matches.arg("nick"), None);
+        let (name, help) = matches.subcommand().unwrap();
+        assert_eq!(name, "help");
+        assert_eq!(help.arg("topic"), Some("options"));
+
+        let matches = command.parse("/query bob hi there").unwrap();
+        assert!(matches.subcommand().is_none());
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        assert_eq!(matches.arg("message"), Some("hi there"));
+
+        assert!(matches!(
+            command.parse("/query"),
+            Err(Error::RequiredArgMissing(_))
+        ));
+        let mixed = command.root_args_when_no_subcommand(false);
+        let matches = mixed.parse("/query help x bob").unwrap();
+        assert_eq!(matches.subcommand().unwrap().1.arg("topic"), Some("x"));
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        assert!(matches!(
+            mixed.parse("/query help x"),
+            Err(Error::RequiredArgMissing(_))
+        ));
+    }
+
+    #[test]
+    fn arg_raw() {
+        let command = Command::new("/msg")
+            .allow_quotes(true)
+            .arg("target", true)
+            .rest_arg("text", false)
+            .arg_default("text", "hi");
+
+        let input = r##"/msg "#a b"  hello   "big  world" "##;
+        let matches = command.parse_borrowed(input).unwrap();
+        assert_eq!(matches.arg("text"), Some("hello big  world"));
+        assert_eq!(matches.arg_raw("text"), Some(r#"hello   "big  world""#));
+        assert_eq!(matches.arg_raw("target"), Some(r##""#a b""##));
+
+        let matches = command.parse_borrowed("/msg bob").unwrap();
+        assert_eq!(matches.arg_raw("text"), None);
+        assert_eq!(matches.arg_raw("other"), None);
+    }
+
+    #[test]
+    fn get_all() {
+        let command = Command::new("/buffer")
+            .flags(&["-all", "-quiet", "-force"])
+            .value_flag("-name")
+            .arg("number", true)
+            .arg("target", false);
+
+        let matches = command
+            .parse_with_preset("/buffer -name a -quiet -name b -quiet 3", &["-force"])
+            .unwrap();
+        assert_eq!(
+            matches.get_all(),
+            vec![
+                ("-force".to_owned(), Value::Flag),
+                (
+                    "-name".to_owned(),
+                    Value::Multi(vec!["a".into(), "b".into()])
+                ),
+                ("-quiet".to_owned(), Value::Flag),
+                ("number".to_owned(), Value::Single("3".into())),
+            ]
+        );
+
+        let matches = command.parse("/buffer -name x 1 2").unwrap();
+        assert_eq!(
+            matches.get_all(),
+            vec![
+                ("-name".to_owned(), Value::Single("x".into())),
+                ("number".to_owned(), Value::Single("1".into())),
+                ("target".to_owned(), Value::Single("2".into())),
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_name() {
+        let command = Command::new("/say").rest_arg("text", false);
+
+        let matches = command.parse("//say hi").unwrap();
+        assert_eq!(matches.arg("text"), Some("//say hi"));
+        assert_eq!(matches.rest("//say hi"), "//say hi");
+        assert_eq!(command.parse("/say hi").unwrap().arg("text"), Some("hi"));
+    }
+
+    #[test]
+    fn lenient_name_match() {
+        let command = Command::new("/say")
+            .rest_arg("text", false)
+            .exact_name_match(false);
+
+        let matches = command.parse("//say hi").unwrap();
+        assert_eq!(matches.arg("text"), Some("hi"));
+        assert_eq!(matches.command_span(), Some(0..5));
+        assert_eq!(command.parse("/say hi").unwrap().arg("text"), Some("hi"));
+        assert_eq!(
+            command.parse("x/say hi").unwrap().arg("text"),
+            Some("x/say hi")
+        );
+    }
+
+    #[test]
+    fn arg_required_unless() {
+        let command = Command::new("/kick")
+            .flag("-silent")
+            .arg("nick", true)
+            .arg("reason", false)
+            .arg_required_unless("reason", "-silent");
+
+        let matches = command.parse("/kick -silent bob").unwrap();
+        assert_eq!(matches.arg("reason"), None);
+        assert!(matches!(
+            command.parse("/kick bob"),
+            Err(Error::RequiredArgMissing(ref arg)) if arg == "reason"
+        ));
+        assert_eq!(
+            command.parse("/kick bob spam").unwrap().arg("reason"),
+            Some("spam")
+        );
+        assert_eq!(
+            command
+                .parse("/kick -silent bob spam")
+                .unwrap()
+                .arg("reason"),
+            Some("spam")
+        );
+    }
+
+    #[test]
+    fn kv_args() {
+        let command = Command::new("/set")
+            .allow_quotes(true)
+            .flag("-temp")
+            .kv_arg("options", true);
+
+        let matches = command
+            .parse(r#"/set -temp a=1 b= c=x=y "d=two words""#)
             .unwrap();
-        assert!(matches.has_flag("-foo"));
-        assert!(!matches.has_flag("-spam"));
+        assert!(matches.has_flag("-temp"));
+        assert_eq!(
+            matches.kv_args(),
+            &[("a", "1"), ("b", ""), ("c", "x=y"), ("d", "two words")]
+        );
+        assert_eq!(matches.arg("options"), Some("a=1 b= c=x=y d=two words"));
 
-        assert_eq!(matches.args(), &["-spam", "bar", "-foo", "baz"]);
+        assert_eq!(
+            matches.to_command_line(),
+            r#"/set -temp a=1 b="" c="x=y" d="two words""#
+        );
+        assert_eq!(command.parse(&matches.to_command_line()).unwrap(), matches);
+
+        assert!(matches!(
+            command.parse("/set a=1 oops"),
+            Err(Error::InvalidKeyValue(ref token, _)) if token == "oops"
+        ));
+        assert!(matches!(
+            command.parse("/set =1"),
+            Err(Error::InvalidKeyValue(..))
+        ));
+        assert!(command.parse("/set").is_err());
     }
 
     #[test]
-    fn whitespace() {
-        let matches = Command::new("/hello")
-            .arg("one", true)
-            .arg("two", true)
-            .parse("/hello   foo  bar");
-        assert!(matches.is_ok());
-        let matches = matches.unwrap();
-        assert_eq!(matches.arg("one").unwrap(), "foo");
-        assert_eq!(matches.arg("two").unwrap(), "bar");
-        assert_eq!(matches.rest("/hello   foo  bar"), "   foo  bar")
+    fn no_flags() {
+        let command = Command::new("/calc")
+            .allow_quotes(true)
+            .no_flags(true)
+            .rest_arg("expression", true);
+
+        assert_eq!(
+            command.parse("/calc -3 - 1.5").unwrap().arg("expression"),
+            Some("-3 - 1.5")
+        );
+        assert!(matches!(
+            command.parse("/calc 1 + -x"),
+            Err(Error::UnexpectedFlag(ref flag, _)) if flag == "-x"
+        ));
+        assert!(matches!(
+            command.parse("/calc --verbose 1"),
+            Err(Error::UnexpectedFlag(..))
+        ));
+        assert_eq!(
+            command.parse(r#"/calc 1 "-x""#).unwrap().arg("expression"),
+            Some("1 -x")
+        );
+        assert_eq!(
+            command.parse("/calc -- -x").unwrap().arg("expression"),
+            Some("-x")
+        );
     }
 
     #[test]
-    fn required_arg() {
-        let matches = Command::new("/hello")
-            .arg("one", true)
-            .arg("two", true)
-            .parse("/hello foo");
-        assert!(matches.is_err());
+    fn arg_info() {
+        let command = Command::new("/join")
+            .arg("channel", true)
+            .arg("key", false)
+            .arg("server", false)
+            .arg_default("server", "libera");
+
+        let matches = command.parse("/join #rust").unwrap();
         assert_eq!(
-            matches.unwrap_err().to_string(),
-            "Missing required arg \"two\""
-        )
+            matches.arg_info("channel"),
+            Some(ArgInfo {
+                value: Some("#rust"),
+                default: None,
+                required: true,
+                supplied: true,
+            })
+        );
+        assert_eq!(
+            matches.arg_info("server"),
+            Some(ArgInfo {
+                value: Some("libera"),
+                default: Some("libera"),
+                required: false,
+                supplied: false,
+            })
+        );
+        assert_eq!(
+            matches.arg_info("key"),
+            Some(ArgInfo {
+                value: None,
+                default: None,
+                required: false,
+                supplied: false,
+            })
+        );
+        assert_eq!(matches.arg_info("other"), None);
+
+        let matches = command.parse("/join #rust secret oftc").unwrap();
+        let server = matches.arg_info("server").unwrap();
+        assert_eq!(server.value, Some("oftc"));
+        assert!(server.supplied);
     }
 
     #[test]
-    fn optional_arg() {
-        let matches = Command::new("/hello")
-            .arg("one", false)
-            .arg("two", false)
-            .parse("/hello foo");
-        assert!(matches.is_ok());
-        assert_eq!(matches.unwrap().args(), &["foo"])
+    fn trim_args() {
+        let command = Command::new("/say")
+            .allow_quotes(true)
+            .trim_args(true)
+            .arg("target", true)
+            .rest_arg("text", false);
+
+        let matches = command.parse("/say \t#rust\t hello\t").unwrap();
+        assert_eq!(matches.arg("target"), Some("#rust"));
+        assert_eq!(matches.arg("text"), Some("hello"));
+
+        let matches = command.parse(r#"/say " #rust " hi " there ""#).unwrap();
+        assert_eq!(matches.arg("target"), Some(" #rust "));
+        assert_eq!(matches.arg("text"), Some("hi  there "));
+
+        let untrimmed = command.trim_args(false);
+        assert_eq!(
+            untrimmed.parse("/say #rust\t").unwrap().arg("target"),
+            Some("#rust\t")
+        );
     }
 
     #[test]
-    fn multiple_flags() {
-        let matches = Command::new("/hello")
-            .flags(&["-foo", "-spam"])
-            .arg("one", true)
-            .arg("two", true)
-            .arg("three", true)
-            .parse("/hello -foo -spam bar -foo baz")
-            .unwrap();
-        assert!(matches.has_flag("-foo"));
-        assert!(matches.has_flag("-spam"));
+    fn subcommand_looks_like_flag() {
+        use crate::BuildError;
 
-        assert_eq!(matches.args(), &["bar", "-foo", "baz"]);
+        let command = Command::new("/tool")
+            .subcommand(Command::new("--help"))
+            .subcommand(Command::new("version").alias("-v"));
+        assert_eq!(
+            command.problems(),
+            &[
+                BuildError::SubcommandLooksLikeFlag("--help".into()),
+                BuildError::SubcommandLooksLikeFlag("-v".into()),
+            ]
+        );
+
+        let plus = Command::new("/mode")
+            .flag_prefixes(&["+"])
+            .subcommand(Command::new("+o"))
+            .subcommand(Command::new("-list"));
+        assert_eq!(
+            plus.validate(),
+            Err(BuildError::SubcommandLooksLikeFlag("+o".into()))
+        );
     }
 
     #[test]
-    fn args() {
-        let matches = Command::new("/hello")
-            .flags(&["-foo", "-spam"])
-            .arg("one", true)
-            .arg("two", true)
-            .arg("three", true)
-            .parse("/hello -foo -spam bar -foo baz")
-            .unwrap();
-        assert!(matches.has_flag("-foo"));
-        assert!(matches.has_flag("-spam"));
+    fn rename_arg() {
+        let command = Command::new("/msg")
+            .arg("target", true)
+            .arg("text", true)
+            .arg("extra", false);
 
-        assert_eq!(matches.args(), &["bar", "-foo", "baz"]);
+        let mut matches = command.parse("/msg bob hi").unwrap();
+        assert!(matches.rename_arg("target", "nick"));
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        assert_eq!(matches.arg("target"), None);
+        assert!(matches.arg_info("nick").unwrap().required);
+
+        assert!(!matches.rename_arg("nick", "text"));
+        assert!(!matches.rename_arg("extra", "nick"));
+        assert!(!matches.rename_arg("missing", "other"));
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        assert_eq!(matches.arg("text"), Some("hi"));
+
+        assert!(matches.rename_arg("extra", "more"));
+        assert!(matches.arg_info("more").is_some());
     }
 
     #[test]
-    fn subcommand() {
-        let matches = Command::new("/hello")
+    fn is_value_flag() {
+        let command = Command::new("/join")
+            .flag("-quiet")
+            .value_flag("--channel")
+            .int_value_flag("--limit", 1..=10)
+            .rest_after_flag("--then");
+
+        assert!(command.is_value_flag("--channel"));
+        assert!(command.is_value_flag("--limit"));
+        assert!(command.is_value_flag("--then"));
+        assert!(!command.is_value_flag("-quiet"));
+        assert!(!command.is_value_flag("--other"));
+    }
+
+    #[test]
+    fn renamed() {
+        let ban = Command::new("/ban")
+            .flag("-all")
+            .arg("nick", false)
+            .subcommand(Command::new("list").arg("channel", false));
+        let quiet = ban.renamed("/quiet");
+
+        let matches = quiet.parse("/quiet -all bob").unwrap();
+        assert_eq!(matches.command(), "/quiet");
+        assert!(matches.has_flag("-all"));
+        assert_eq!(matches.arg("nick"), Some("bob"));
+        let matches = quiet.parse("/quiet list #rust").unwrap();
+        assert_eq!(
+            matches.subcommand().unwrap().1.arg("channel"),
+            Some("#rust")
+        );
+
+        assert_eq!(ban.get_name(), "/ban");
+        assert_eq!(ban.parse("/ban bob").unwrap().arg("nick"), Some("bob"));
+        assert_eq!(ban.parse("/quiet bob").unwrap().arg("nick"), Some("/quiet"));
+    }
+
+    #[test]
+    fn arg_as_path() {
+        use std::path::{Path, PathBuf};
+
+        let command = Command::new("/upload").arg("file", true);
+        let path = |input: &str| command.parse(input).unwrap().arg_as_path("file");
+
+        let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"));
+        let expected = |rest: &str, raw: &str| match &home {
+            Some(home) => Path::new(home).join(rest),
+            None => PathBuf::from(raw),
+        };
+        assert_eq!(
+            path("/upload ~/pic.png"),
+            Some(expected("pic.png", "~/pic.png"))
+        );
+        assert_eq!(path("/upload ~"), Some(expected("", "~")));
+
+        assert_eq!(path("/upload /tmp/pic.png"), Some("/tmp/pic.png".into()));
+        assert_eq!(path("/upload ~bob/pic.png"), Some("~bob/pic.png".into()));
+        assert_eq!(path("/upload pic~.png"), Some("pic~.png".into()));
+        let optional = Command::new("/upload").arg("file", false);
+        assert_eq!(optional.parse("/upload").unwrap().arg_as_path("file"), None);
+    }
+
+    #[test]
+    fn walk() {
+        let command = Command::new("/discord")
             .subcommand(
-                Command::new("subcommand")
-                    .flags(&["-foo", "-spam"])
-                    .arg("one", true)
-                    .arg("two", true)
-                    .arg("three", true),
+                Command::new("server")
+                    .subcommand(Command::new("add"))
+                    .subcommand(Command::new("remove")),
             )
-            .parse("/hello subcommand -foo -spam bar -foo baz")
+            .subcommand(Command::new("join"));
+
+        let mut visited = Vec::new();
+        command.walk(&mut |command, depth| visited.push((command.get_name().to_owned(), depth)));
+        assert_eq!(
+            visited,
+            &[
+                ("/discord".to_owned(), 0),
+                ("server".to_owned(), 1),
+                ("add".to_owned(), 2),
+                ("remove".to_owned(), 2),
+                ("join".to_owned(), 1),
+            ]
+        );
+
+        let mut count = 0;
+        Command::new("/nick").walk(&mut |_, _| count += 1);
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn escaped_flag() {
+        for quotes in [false, true] {
+            let command = Command::new("/say")
+                .allow_quotes(quotes)
+                .flag("-notaflag")
+                .unknown_flags(Policy::Error)
+                .rest_arg("text", false);
+
+            let matches = command.parse(r"/say \-notaflag -x").unwrap();
+            assert!(!matches.has_flag("-notaflag"));
+            assert_eq!(matches.args(), &["-notaflag -x"]);
+            assert!(command
+                .parse("/say -notaflag")
+                .unwrap()
+                .has_flag("-notaflag"));
+        }
+    }
+
+    #[test]
+    fn lookup_priority() {
+        let command = Command::new("/log").value_flag("level").arg("level", false);
+
+        let matches = command.parse("/log level debug info").unwrap();
+        assert_eq!(matches.get("level"), Some(Value::Single("debug".into())));
+        assert_eq!(matches.get("other"), None);
+        assert_eq!(matches.get_all()[0].1, Value::Single("debug".into()));
+
+        let matches = command
+            .lookup_priority(Priority::ArgsFirst)
+            .parse("/log level debug info")
             .unwrap();
+        assert_eq!(matches.get("level"), Some(Value::Single("info".into())));
+        assert_eq!(
+            matches.get_all(),
+            vec![
+                ("level".to_owned(), Value::Single("info".into())),
+                ("level".to_owned(), Value::Single("debug".into())),
+            ]
+        );
+    }
 
-        assert!(matches.subcommand().is_some());
-        assert_eq!(matches.subcommand().unwrap().0, "subcommand");
-        assert!(matches.subcommand().unwrap().1.has_flag("-foo"));
-        assert!(matches.subcommand().unwrap().1.has_flag("-spam"));
+    #[test]
+    fn single_rest_arg() {
+        use crate::BuildError;
+
+        let command = Command::new("/me").flag("-quiet").single_rest_arg("action");
+        assert_eq!(command.validate(), Ok(()));
+
+        let matches = command.parse("/me -quiet waves  hello").unwrap();
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.args(), &["waves hello"]);
+        assert_eq!(matches.arg("action"), Some("waves hello"));
+        assert_eq!(command.parse("/me").unwrap().arg("action"), None);
+
+        let command = Command::new("/me")
+            .arg("target", true)
+            .single_rest_arg("action");
         assert_eq!(
-            matches.subcommand().unwrap().1.args(),
-            &["bar", "-foo", "baz"]
+            command.validate(),
+            Err(BuildError::SingleRestArgNotAlone("action".into()))
+        );
+        assert_eq!(
+            command.parse("/me bob waves").unwrap().arg("target"),
+            Some("bob")
+        );
+        assert_eq!(
+            Command::new("/me")
+                .shared_arg("buffer", false)
+                .single_rest_arg("action")
+                .problems(),
+            &[BuildError::SingleRestArgNotAlone("action".into())]
         );
     }
 
     #[test]
-    fn subcommand2() {
-        let matches = Command::new("/discord")
+    fn escaped_flag_values() {
+        let command = Command::new("/send")
+            .allow_quotes(true)
+            .value_flag("--msg")
+            .csv_value_flag("--to")
+            .rest_after_flag("--then");
+
+        for input in [
+            r#"/send --msg "a b""#,
+            r#"/send --msg="a b""#,
+            r"/send --msg=a\ b",
+            r"/send --msg a\ b",
+            r"/send --msg 'a b'",
+        ] {
+            assert_eq!(
+                command.parse(input).unwrap().flag_value("--msg"),
+                Some("a b")
+            );
+        }
+
+        let matches = command.parse(r##"/send --to "#a, #b",c\ d"##).unwrap();
+        assert_eq!(matches.flag_csv("--to").unwrap(), &["#a", "#b", "c d"]);
+
+        let matches = command.parse(r#"/send --then say "x  y" a\ \ b"#).unwrap();
+        assert_eq!(matches.flag_value("--then"), Some("say x  y a  b"));
+    }
+
+    #[test]
+    fn flag_in_multiple_groups() {
+        use crate::BuildError;
+
+        let command = Command::new("/connect")
+            .flags(&["-tls", "-plain", "-ipv4", "-ipv6"])
+            .exclusive_flags(&["-tls", "-plain"])
+            .exclusive_flags(&["-ipv4", "-ipv6"])
+            .exclusive_flags(&["-plain", "-ipv6"])
+            .exclusive_flags(&["-tls", "-plain"]);
+
+        assert_eq!(
+            command.problems(),
+            &[
+                BuildError::FlagInMultipleGroups("-plain".into()),
+                BuildError::FlagInMultipleGroups("-ipv6".into()),
+                BuildError::FlagInMultipleGroups("-tls".into()),
+            ]
+        );
+        assert!(Command::new("/connect")
+            .flags(&["-tls", "-plain"])
+            .exclusive_flags(&["-tls", "-plain"])
+            .require_one_of(&["-tls", "-plain"])
+            .validate()
+            .is_ok());
+    }
+
+    #[test]
+    fn parse_argv() {
+        let command = Command::new("/discord")
+            .alias("/dc")
+            .subcommand(Command::new("join").flag("-quiet").arg("channel", true))
+            .subcommand(Command::new("say").rest_arg("text", true));
+
+        let matches = command
+            .parse_argv(&["/dc", "join", "-quiet", "#general"])
+            .unwrap();
+        assert_eq!(matches.command(), "/discord");
+        let (name, join) = matches.subcommand().unwrap();
+        assert_eq!(name, "join");
+        assert!(join.has_flag("-quiet"));
+        assert_eq!(join.arg("channel"), Some("#general"));
+
+        let matches = command
+            .parse_argv(&["/discord", "say", "/discord", "hi"])
+            .unwrap();
+        assert_eq!(
+            matches.subcommand().unwrap().1.arg("text"),
+            Some("/discord hi")
+        );
+        assert_eq!(matches.rest("/discord say /discord hi"), " say /discord hi");
+
+        assert!(command
+            .parse_argv(&["/discord"])
+            .unwrap()
+            .subcommand()
+            .is_none());
+        assert!(command.parse_argv(&[]).unwrap().subcommand().is_none());
+    }
+
+    #[test]
+    fn ignore_flag_case() {
+        let command = Command::new("/join")
+            .flag("-foo")
+            .value_flag("--channel")
+            .exclusive_flags(&["-foo", "-bar"])
+            .flag("-bar")
+            .ignore_flag_case(true);
+
+        let matches = command.parse("/join -Foo --CHANNEL=#a x").unwrap();
+        assert!(matches.has_flag("-foo"));
+        assert_eq!(matches.flag_value("--channel"), Some("#a"));
+        assert_eq!(matches.flags(), &["-Foo", "--CHANNEL"]);
+        assert!(command.parse("/join -FOO -Bar").is_err());
+
+        let strict = Command::new("/join").flag("-foo");
+        let matches = strict.parse("/join -foo -Foo").unwrap();
+        assert_eq!(matches.flags(), &["-foo"]);
+    }
+
+    #[test]
+    fn to_json() {
+        let command = Command::new("/server")
+            .allow_quotes(true)
+            .shared_arg("network", true)
             .subcommand(
-                Command::new("subcommand")
-                    .flags(&["-foo", "-spam"])
-                    .arg("one", true)
-                    .arg("two", true)
-                    .arg("three", true),
-            )
-            .parse("/discord subcommand -foo -spam bar -foo baz")
+                Command::new("add")
+                    .flag("-v")
+                    .flag("-a")
+                    .arg("host", true)
+                    .arg("note", false),
+            );
+
+        let matches = command
+            .parse("/server libera add -v -a irc.libera.chat \"say \\\"hi\\\"\\\\\t\"")
             .unwrap();
+        assert_eq!(
+            matches.to_json(),
+            r#"{"command":"/server","flags":[],"args":{"network":"libera"},"subcommand":{"command":"add","flags":["-a","-v"],"args":{"host":"irc.libera.chat","note":"say \"hi\"\\\t"},"subcommand":null}}"#
+        );
+        assert_eq!(
+            Command::new("/x")
+                .arg("a", true)
+                .parse("/x \u{1}")
+                .unwrap()
+                .to_json(),
+            r#"{"command":"/x","flags":[],"args":{"a":"\u0001"},"subcommand":null}"#
+        );
+    }
 
-        assert!(matches.subcommand().is_some());
-        assert_eq!(matches.subcommand().unwrap().0, "subcommand");
-        assert!(matches.subcommand().unwrap().1.has_flag("-foo"));
-        assert!(matches.subcommand().unwrap().1.has_flag("-spam"));
+    #[test]
+    fn incomplete_value_flags() {
+        let command = Command::new("/join")
+            .flag("-quiet")
+            .value_flag("--channel")
+            .value_flag("--key");
+
+        let (matches, errors) = command.parse_lossy("/join --channel -quiet --key");
+        assert_eq!(matches.incomplete_value_flags(), &["--channel", "--key"]);
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.flag_value("--channel"), None);
+        assert!(matches!(
+            errors.as_slice(),
+            [Error::FlagValueMissing(..), Error::FlagValueMissing(..)]
+        ));
+
+        assert!(matches!(
+            command.parse("/join --channel -quiet"),
+            Err(Error::FlagValueMissing(flag, _)) if flag == "--channel"
+        ));
+        let matches = command.parse("/join --channel #rust").unwrap();
+        assert_eq!(matches.flag_value("--channel"), Some("#rust"));
+        assert!(matches.incomplete_value_flags().is_empty());
+    }
+
+    #[test]
+    fn subcommand_name() {
+        let command = Command::new("/server")
+            .subcommand(Command::new("add").arg("host", true))
+            .subcommand(Command::new("list"));
+
+        let matches = command.parse("/server add irc.libera.chat").unwrap();
+        let name = matches.subcommand_name().unwrap();
+        assert_eq!(name, "add");
+        let stored = &matches.subcommand_match.as_ref().unwrap().0;
+        assert!(std::ptr::eq(name, stored.as_str()));
+
+        assert_eq!(command.parse("/server").unwrap().subcommand_name(), None);
+    }
+
+    #[test]
+    fn multi_value_flag() {
+        let command = Command::new("/invite")
+            .flag("-quiet")
+            .multi_value_flag("--to")
+            .arg("channel", false)
+            .arg("message", false);
+
+        let matches = command.parse("/invite --to alice bob -- #rust hi").unwrap();
+        assert_eq!(matches.flag_values("--to"), &["alice", "bob"]);
+        assert_eq!(matches.args(), &["#rust", "hi"]);
+
+        let matches = command
+            .parse("/invite --to alice bob -quiet #rust")
+            .unwrap();
+        assert_eq!(matches.flag_values("--to"), &["alice", "bob"]);
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.args(), &["#rust"]);
+
+        let tree = command.parse_ast("/invite --to a b").unwrap();
+        let spans: Vec<_> = tree.flags.iter().map(|flag| flag.span.clone()).collect();
+        assert_eq!(spans, &[8..14, 15..16]);
+
+        assert!(matches!(
+            command.parse("/invite --to -- #rust"),
+            Err(Error::FlagValueMissing(..))
+        ));
+    }
+
+    #[test]
+    fn try_parse() {
+        let command = Command::new("/join")
+            .alias("/j")
+            .allow_quotes(true)
+            .arg("channel", true);
+
+        let matches = command.try_parse("/j #rust").unwrap().unwrap();
+        assert_eq!(matches.arg("channel"), Some("#rust"));
+        assert!(command.try_parse("/part #rust").is_none());
+        assert!(command.try_parse("#rust").is_none());
+        assert!(command.try_parse("").is_none());
+        assert!(matches!(
+            command.try_parse("/join"),
+            Some(Err(Error::RequiredArgMissing(_)))
+        ));
+        assert!(matches!(
+            command.try_parse(r##"/join "#rust"##),
+            Some(Err(Error::UnbalancedQuote))
+        ));
+        assert!(command.try_parse(r##"/part "#rust"##).is_none());
+    }
+
+    #[test]
+    fn flag_value_map() {
+        let command = Command::new("/invite")
+            .flag("-quiet")
+            .value_flag("--channel")
+            .multi_value_flag("--to");
+
+        let matches = command
+            .parse("/invite --to alice bob -quiet --channel #rust")
+            .unwrap();
         assert_eq!(
-            matches.subcommand().unwrap().1.args(),
-            &["bar", "-foo", "baz"]
+            matches.flag_value_map(),
+            &[("--to", vec!["alice", "bob"]), ("--channel", vec!["#rust"])]
+        );
+        assert!(command
+            .parse("/invite -quiet")
+            .unwrap()
+            .flag_value_map()
+            .is_empty());
+    }
+
+    #[test]
+    fn name_spans() {
+        let command = Command::new("/server")
+            .allow_quotes(true)
+            .subcommand(Command::new("add").arg("host", true));
+
+        let matches = command.parse("  /server  add irc.libera.chat").unwrap();
+        assert_eq!(matches.command_span(), Some(2..9));
+        assert_eq!(matches.subcommand_span(), Some(11..14));
+
+        let matches = command.parse("add irc.libera.chat").unwrap();
+        assert_eq!(matches.command_span(), None);
+        assert_eq!(matches.subcommand_span(), Some(0..3));
+        assert_eq!(command.parse("/server").unwrap().subcommand_span(), None);
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn arg_pattern() {
+        let command = Command::new("/remind")
+            .arg_pattern("time", true, r"\d\d:\d\d")
+            .rest_arg("message", false);
+
+        let matches = command.parse("/remind 12:30 lunch").unwrap();
+        assert_eq!(matches.arg("time"), Some("12:30"));
+
+        let error = command.parse("/remind 12:30pm lunch").unwrap_err();
+        assert!(matches!(&error, Error::InvalidArgValue { arg, .. } if arg == "time"));
+        assert_eq!(
+            error.to_string(),
+            r#"Invalid value "12:30pm" for "time": must match \d\d:\d\d"#
         );
+        assert!(command.parse("/remind noon").is_err());
+        assert_eq!(command.validate(), Ok(()));
+
+        let invalid = Command::new("/remind").arg_pattern("time", true, r"(\d");
+        assert!(matches!(
+            invalid.validate(),
+            Err(crate::BuildError::InvalidPattern { ref arg, .. }) if arg == "time"
+        ));
+        assert!(invalid.parse("/remind noon").is_ok());
+    }
+
+    #[test]
+    fn validate_with() {
+        fn output_needs_file(parsed: &crate::ParsedCommand) -> Result<(), String> {
+            if parsed.has_flag("-o") && parsed.arg("file").is_none() {
+                Err("-o needs a file".to_owned())
+            } else {
+                Ok(())
+            }
+        }
+
+        let command = Command::new("/log")
+            .flag("-o")
+            .arg("file", false)
+            .validate_with(output_needs_file);
+
+        assert!(command.parse("/log").is_ok());
+        assert!(command.parse("/log -o weechat.log").is_ok());
+        let error = command.parse("/log -o").unwrap_err();
+        assert!(matches!(&error, Error::ValidationFailed(message) if message == "-o needs a file"));
+        assert_eq!(error.to_string(), "-o needs a file");
+
+        let (_, errors) = command.parse_lossy("/log -o");
+        assert_eq!(errors.len(), 1);
     }
 }
@@ -0,0 +1,199 @@
+use std::str::FromStr;
+
+use crate::{BuildError, Command};
+
+/// Build a command from a usage-like spec such as `/say -quiet --to= <target> [message*]`.
+///
+/// The first word is the command name, followed by any of:
+///
+/// * `-flag`: a flag, any token starting with `-`
+/// * `-flag=`: a value flag
+/// * `<name>`: a required arg
+/// * `[name]`: an optional arg
+/// * `<name*>` or `[name*]`: an arg capturing all remaining tokens, only allowed last
+impl FromStr for Command {
+    type Err = BuildError;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        let mut tokens = spec.split(' ').filter(|token| !token.is_empty());
+
+        let name = tokens
+            .next()
+            .ok_or_else(|| BuildError::InvalidSpec(spec.to_owned()))?;
+        let mut command = Command::new(name);
+
+        for token in tokens {
+            if let Some(rule) = command.args.last().filter(|rule| rule.rest) {
+                return Err(BuildError::RestArgNotLast(rule.name.clone()));
+            }
+
+            command = if let Some(flag) = token.strip_suffix('=').filter(|_| token.starts_with('-'))
+            {
+                command.value_flag(flag)
+            } else if token.starts_with('-') {
+                command.flag(token)
+            } else if let Some(arg) = token.strip_prefix('<').and_then(|t| t.strip_suffix('>')) {
+                add_arg(command, arg, true, token)?
+            } else if let Some(arg) = token.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+                add_arg(command, arg, false, token)?
+            } else {
+                return Err(BuildError::InvalidSpec(token.to_owned()));
+            };
+        }
+
+        Ok(command)
+    }
+}
+
+/// One item of a command definition for [`Command::build_from`].
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum Spec {
+    /// A flag, like [`Command::flag`].
+    Flag(String),
+    /// A flag taking a value, like [`Command::value_flag`].
+    ValueFlag(String),
+    /// A positional arg, like [`Command::arg`].
+    Arg { name: String, required: bool },
+    /// A subcommand, like [`Command::subcommand`].
+    Sub(Box<Command>),
+}
+
+impl Command {
+    /// Build a command from a sequence of definition items, checking it with
+    /// [`validate`](Command::validate).
+    ///
+    /// This is a middle ground between the chained builder and the string spec for commands
+    /// defined by data.
+    pub fn build_from<I>(name: &str, specs: I) -> Result<Command, BuildError>
+    where
+        I: IntoIterator<Item = Spec>,
+    {
+        let command = specs
+            .into_iter()
+            .fold(Command::new(name), |command, spec| match spec {
+                Spec::Flag(flag) => command.flag(&flag),
+                Spec::ValueFlag(flag) => command.value_flag(&flag),
+                Spec::Arg { name, required } => command.arg(&name, required),
+                Spec::Sub(subcommand) => command.subcommand(*subcommand),
+            });
+
+        command.validate()?;
+        Ok(command)
+    }
+}
+
+fn add_arg(
+    command: Command,
+    arg: &str,
+    required: bool,
+    token: &str,
+) -> Result<Command, BuildError> {
+    let (name, rest) = match arg.strip_suffix('*') {
+        Some(name) => (name, true),
+        None => (arg, false),
+    };
+
+    if name.is_empty() || name.contains('*') {
+        return Err(BuildError::InvalidSpec(token.to_owned()));
+    }
+
+    Ok(if rest {
+        command.rest_arg(name, required)
+    } else {
+        command.arg(name, required)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{BuildError, Command, Spec};
+
+    #[test]
+    fn parse_spec() {
+        let command: Command = "/msg -quiet --server= <target> [message*]".parse().unwrap();
+
+        let matches = command
+            .parse("/msg -quiet --server=libera #rust hello there")
+            .unwrap();
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.flag_value("--server"), Some("libera"));
+        assert_eq!(matches.arg("target"), Some("#rust"));
+        assert_eq!(matches.arg("message"), Some("hello there"));
+
+        assert!(command.parse("/msg").is_err());
+    }
+
+    #[test]
+    fn variadic() {
+        let command: Command = "/say <message*>".parse().unwrap();
+        assert_eq!(
+            command.parse("/say a b c").unwrap().arg("message"),
+            Some("a b c")
+        );
+    }
+
+    #[test]
+    fn invalid_spec() {
+        assert_eq!(
+            "/say <message*> [other]".parse::<Command>().err(),
+            Some(BuildError::RestArgNotLast("message".into()))
+        );
+        assert_eq!(
+            "/say message".parse::<Command>().err(),
+            Some(BuildError::InvalidSpec("message".into()))
+        );
+        assert_eq!(
+            "/say <*>".parse::<Command>().err(),
+            Some(BuildError::InvalidSpec("<*>".into()))
+        );
+        assert!("".parse::<Command>().is_err());
+    }
+
+    #[test]
+    fn build_from_specs() {
+        let specs = vec![
+            Spec::Flag("-quiet".into()),
+            Spec::ValueFlag("--server".into()),
+            Spec::Arg {
+                name: "target".into(),
+                required: true,
+            },
+        ];
+        let command = Command::build_from("/msg", specs).unwrap();
+
+        let matches = command.parse("/msg -quiet --server libera #rust").unwrap();
+        assert!(matches.has_flag("-quiet"));
+        assert_eq!(matches.flag_value("--server"), Some("libera"));
+        assert_eq!(matches.arg("target"), Some("#rust"));
+
+        let command = Command::build_from("/msg", vec![Spec::Sub(Box::new(Command::new("list")))]);
+        assert!(command
+            .unwrap()
+            .parse("/msg list")
+            .unwrap()
+            .subcommand()
+            .is_some());
+
+        let mixed = vec![
+            Spec::Arg {
+                name: "target".into(),
+                required: true,
+            },
+            Spec::Sub(Box::new(Command::new("list"))),
+        ];
+        assert_eq!(
+            Command::build_from("/msg", mixed).err(),
+            Some(BuildError::SubcommandAndArgsConflict("/msg".into()))
+        );
+
+        let duplicate = vec![
+            Spec::Sub(Box::new(Command::new("list"))),
+            Spec::Sub(Box::new(Command::new("list"))),
+        ];
+        assert_eq!(
+            Command::build_from("/msg", duplicate).err(),
+            Some(BuildError::DuplicateSubcommand("list".into()))
+        );
+    }
+}
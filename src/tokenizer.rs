@@ -0,0 +1,389 @@
+use std::borrow::Cow;
+use std::ops::Range;
+
+use crate::Error;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Token {
+    pub value: String,
+    /// The token started with a quote, it is always a plain value and never a flag or a name.
+    pub literal: bool,
+    /// Byte range of the token in the input, including any quotes.
+    pub span: Range<usize>,
+}
+
+impl Token {
+    /// A token that isn't quoted, `\-foo` is read as a literal `-foo`.
+    pub fn plain(value: String, span: Range<usize>) -> Self {
+        let mut token = Self {
+            value,
+            literal: false,
+            span,
+        };
+        token.unescape();
+        token
+    }
+
+    /// Turn a leading `\-` into a literal `-`, so a single token can start with a dash without
+    /// being a flag.
+    fn unescape(&mut self) {
+        if !self.literal && self.value.starts_with("\\-") {
+            self.value.remove(0);
+            self.literal = true;
+        }
+    }
+}
+
+/// Split on single spaces, dropping the empty tokens caused by repeated spaces.
+pub(crate) fn split(input: &str) -> Vec<Token> {
+    let mut start = 0;
+    let mut tokens = Vec::new();
+
+    for token in input.split(' ') {
+        if !token.is_empty() {
+            tokens.push(Token::plain(token.to_owned(), start..(start + token.len())));
+        }
+        start += token.len() + 1;
+    }
+
+    tokens
+}
+
+/// Turn already split values into tokens, with spans as if they were joined by single spaces.
+pub(crate) fn from_values<I>(values: I) -> Vec<Token>
+where
+    I: Iterator<Item = String>,
+{
+    let mut start = 0;
+
+    values
+        .filter(|value| !value.is_empty())
+        .map(|value| {
+            let span = start..(start + value.len());
+            start = span.end + 1;
+            Token::plain(value, span)
+        })
+        .collect()
+}
+
+/// Like [`from_values`], but reusing the tokens and their strings left over from a previous call.
+pub(crate) fn refill(tokens: &mut Vec<Token>, values: &[&str]) {
+    let mut start = 0;
+    let mut len = 0;
+
+    for value in values.iter().filter(|value| !value.is_empty()) {
+        let span = start..(start + value.len());
+        start = span.end + 1;
+
+        match tokens.get_mut(len) {
+            Some(token) => {
+                token.value.clear();
+                token.value.push_str(value);
+                token.literal = false;
+                token.span = span;
+                token.unescape();
+            }
+            None => tokens.push(Token::plain((*value).to_owned(), span)),
+        }
+        len += 1;
+    }
+
+    tokens.truncate(len);
+}
+
+/// Split on spaces while keeping quoted sections together.
+///
+/// Double quoted sections support `\"` and `\\` escapes, single quoted sections are taken
+/// verbatim. Outside quotes a backslash escapes a space, a quote or another backslash, so
+/// `--message=a\ b` reads the same as `--message="a b"`. Quotes may start in the middle of a
+/// token, but only a token that starts with a quote, or with `\-`, is treated as literal.
+pub(crate) fn tokenize(input: &str) -> Result<Vec<Token>, Error> {
+    let mut tokens = Vec::new();
+    let mut current: Option<Token> = None;
+    let mut chars = input.char_indices();
+
+    while let Some((start, c)) = chars.next() {
+        match c {
+            ' ' => tokens.extend(current.take()),
+            '\\' => match chars.clone().next().map(|(_, c)| c) {
+                Some(escaped) if is_escapable(escaped) => {
+                    chars.next();
+                    current
+                        .get_or_insert_with(|| Token::plain(String::new(), start..start))
+                        .value
+                        .push(escaped);
+                }
+                Some('-') if current.is_none() => {
+                    current = Some(Token {
+                        value: String::new(),
+                        literal: true,
+                        span: start..start,
+                    });
+                }
+                _ => current
+                    .get_or_insert_with(|| Token::plain(String::new(), start..start))
+                    .value
+                    .push('\\'),
+            },
+            '"' | '\'' => {
+                let token = current.get_or_insert_with(|| Token {
+                    value: String::new(),
+                    literal: true,
+                    span: start..start,
+                });
+
+                loop {
+                    match chars.next().map(|(_, c)| c) {
+                        Some(end) if end == c => break,
+                        Some('\\') if c == '"' => match chars.next().map(|(_, c)| c) {
+                            Some(escaped @ '"') | Some(escaped @ '\\') => token.value.push(escaped),
+                            Some(other) => {
+                                token.value.push('\\');
+                                token.value.push(other);
+                            }
+                            None => return Err(Error::UnbalancedQuote),
+                        },
+                        Some(other) => token.value.push(other),
+                        None => return Err(Error::UnbalancedQuote),
+                    }
+                }
+            }
+            c => current
+                .get_or_insert_with(|| Token::plain(String::new(), start..start))
+                .value
+                .push(c),
+        }
+
+        if let Some(token) = current.as_mut() {
+            token.span.end = chars.offset();
+        }
+    }
+    tokens.extend(current);
+
+    Ok(tokens)
+}
+
+/// Characters a backslash outside quotes escapes in [`tokenize`].
+fn is_escapable(c: char) -> bool {
+    matches!(c, ' ' | '"' | '\'' | '\\')
+}
+
+/// Split `input` on `separator`, skipping separators inside quotes when `quotes` is set.
+///
+/// Quotes follow the rules of [`tokenize`], and segments that are empty or only spaces are
+/// dropped.
+pub(crate) fn split_commands(
+    input: &str,
+    separator: char,
+    quotes: bool,
+) -> Result<Vec<&str>, Error> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut chars = input.char_indices();
+
+    while let Some((index, c)) = chars.next() {
+        match c {
+            '\\' if quotes && chars.clone().next().is_some_and(|(_, c)| is_escapable(c)) => {
+                chars.next();
+            }
+            '"' | '\'' if quotes => loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some(end) if end == c => break,
+                    Some('\\') if c == '"' => {
+                        if chars.next().is_none() {
+                            return Err(Error::UnbalancedQuote);
+                        }
+                    }
+                    Some(_) => {}
+                    None => return Err(Error::UnbalancedQuote),
+                }
+            },
+            c if c == separator => {
+                segments.push(&input[start..index]);
+                start = index + c.len_utf8();
+            }
+            _ => {}
+        }
+    }
+    segments.push(&input[start..]);
+
+    segments.retain(|segment| !segment.trim_matches(' ').is_empty());
+    Ok(segments)
+}
+
+/// The byte offset of a quote that is never closed, following the rules of [`tokenize`].
+pub(crate) fn unclosed_quote(input: &str) -> Option<usize> {
+    let mut chars = input.char_indices();
+
+    while let Some((start, c)) = chars.next() {
+        if c == '\\' {
+            if chars.clone().next().is_some_and(|(_, c)| is_escapable(c)) {
+                chars.next();
+            }
+        } else if c == '"' || c == '\'' {
+            loop {
+                match chars.next().map(|(_, c)| c) {
+                    Some(end) if end == c => break,
+                    Some('\\') if c == '"' => {
+                        if chars.next().is_none() {
+                            return Some(start);
+                        }
+                    }
+                    Some(_) => {}
+                    None => return Some(start),
+                }
+            }
+        }
+    }
+
+    None
+}
+
+/// Quote a value so that [`tokenize`] reads it back as a single literal token.
+///
+/// Values are left bare when that is unambiguous.
+pub(crate) fn quote(value: &str) -> Cow<'_, str> {
+    let needs_quotes =
+        value.is_empty() || value.starts_with('-') || value.contains([' ', '"', '\'', '\\', '=']);
+
+    if needs_quotes {
+        let mut quoted = String::with_capacity(value.len() + 2);
+        quoted.push('"');
+        for c in value.chars() {
+            if c == '"' || c == '\\' {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        Cow::Owned(quoted)
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{quote, split, split_commands, tokenize, unclosed_quote, Token};
+
+    fn values(input: &str) -> Vec<String> {
+        tokenize(input)
+            .unwrap()
+            .into_iter()
+            .map(|token| token.value)
+            .collect()
+    }
+
+    #[test]
+    fn quoted_sections() {
+        assert_eq!(
+            values(r#"/say "hello world"  'it''s'"#),
+            &["/say", "hello world", "its"]
+        );
+        assert_eq!(
+            values(r#"--message="a b" "" x"#),
+            &["--message=a b", "", "x"]
+        );
+        assert_eq!(values(r#""a \"b\" \\ \n""#), &[r#"a "b" \ \n"#]);
+    }
+
+    #[test]
+    fn literal_tokens() {
+        assert_eq!(
+            tokenize(r#""-foo" -foo"#).unwrap(),
+            &[
+                Token {
+                    value: "-foo".into(),
+                    literal: true,
+                    span: 0..6,
+                },
+                Token {
+                    value: "-foo".into(),
+                    literal: false,
+                    span: 7..11,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn escaped_dash() {
+        let tokens = tokenize(r#"\-a "\-b" \\-c -d"#).unwrap();
+        let values: Vec<(&str, bool)> = tokens
+            .iter()
+            .map(|token| (token.value.as_str(), token.literal))
+            .collect();
+        assert_eq!(
+            values,
+            &[("-a", true), ("\\-b", true), ("\\-c", false), ("-d", false)]
+        );
+        assert_eq!(tokenize(r#"\-a"#).unwrap()[0].span, 0..3);
+        assert_eq!(split(r#"x \-a"#)[1].value, "-a");
+        assert!(split(r#"x \-a"#)[1].literal);
+    }
+
+    #[test]
+    fn escapes_outside_quotes() {
+        assert_eq!(
+            values(r#"--msg=a\ b \"x\" \\ c\d"#),
+            &["--msg=a b", r#""x""#, r"\", r"c\d"]
+        );
+        assert!(tokenize(r#"\"a"#).is_ok());
+        assert_eq!(unclosed_quote(r#"\"a "b"#), Some(4));
+        assert_eq!(
+            split_commands(r#"a\"; b"#, ';', true).unwrap(),
+            &[r#"a\""#, " b"]
+        );
+    }
+
+    #[test]
+    fn unbalanced() {
+        assert!(tokenize(r#"/say "hello"#).is_err());
+        assert!(tokenize("/say 'hello").is_err());
+        assert!(tokenize(r#"/say "hello\""#).is_err());
+    }
+
+    #[test]
+    fn quote_round_trip() {
+        for &value in &["plain", "", "two words", "-flag", "a=b", r#"q"u'o\te"#] {
+            let quoted = quote(value);
+            assert_eq!(
+                tokenize(&quoted).unwrap(),
+                &[Token {
+                    value: value.into(),
+                    literal: quoted != value,
+                    span: 0..quoted.len(),
+                }]
+            );
+        }
+    }
+
+    #[test]
+    fn command_separators() {
+        assert_eq!(
+            split_commands(r#"/say "a; b"; /quit"#, ';', true).unwrap(),
+            &[r#"/say "a; b""#, " /quit"]
+        );
+        assert_eq!(
+            split_commands(r#"/say 'a;b' "\";x" ;; "#, ';', true).unwrap(),
+            &[r#"/say 'a;b' "\";x" "#]
+        );
+        assert_eq!(
+            split_commands(r#"/say "a; b""#, ';', false).unwrap(),
+            &[r#"/say "a"#, r#" b""#]
+        );
+        assert!(split_commands(r#"/say "a; /quit"#, ';', true).is_err());
+        assert!(split_commands("/say 'a; /quit", ';', true).is_err());
+    }
+
+    #[test]
+    fn spans() {
+        let spans = |tokens: Vec<Token>| -> Vec<_> { tokens.into_iter().map(|t| t.span).collect() };
+
+        assert_eq!(spans(split(" /say  hi ")), &[1..5, 7..9]);
+        assert_eq!(
+            spans(tokenize(r#"/say "a b"  --m='x y' é"#).unwrap()),
+            &[0..4, 5..10, 12..21, 22..24]
+        );
+    }
+}
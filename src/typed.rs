@@ -0,0 +1,99 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{Command, Error, ParsedArg, ParsedCommand};
+
+/// Conversion of the args of a parsed command into a tuple, see [`Command::parse_typed`].
+///
+/// This is implemented for tuples of one to six [`FromStr`] types, filled from the args in the
+/// same order as [`ParsedCommand::args`].
+pub trait FromArgs: Sized {
+    fn from_args(parsed: &ParsedCommand) -> Result<Self, Error>;
+}
+
+impl Command {
+    /// Parse `input` and convert its args into a tuple, like `(u16, String)`.
+    ///
+    /// Fails with [`Error::TooFewArgs`] or [`Error::TooManyArgs`] if the number of args doesn't
+    /// match the tuple, and with [`Error::InvalidArgValue`] if one doesn't parse.
+    pub fn parse_typed<T: FromArgs>(&self, input: &str) -> Result<T, Error> {
+        T::from_args(&self.parse(input)?)
+    }
+}
+
+fn check_arity(parsed: &ParsedCommand, count: usize) -> Result<(), Error> {
+    let got = parsed.args.len();
+    if got < count {
+        Err(Error::TooFewArgs { min: count, got })
+    } else if got > count {
+        Err(Error::TooManyArgs { max: count, got })
+    } else {
+        Ok(())
+    }
+}
+
+fn parse_arg<T>(arg: &ParsedArg) -> Result<T, Error>
+where
+    T: FromStr,
+    T::Err: fmt::Display,
+{
+    arg.value
+        .parse()
+        .map_err(|error: T::Err| Error::InvalidArgValue {
+            arg: arg.name.clone(),
+            value: arg.value.clone(),
+            message: error.to_string(),
+            span: Some(arg.span.clone()).filter(|_| !arg.defaulted),
+        })
+}
+
+macro_rules! tuple_from_args {
+    ($count:expr; $($name:ident),+) => {
+        impl<$($name),+> FromArgs for ($($name,)+)
+        where
+            $($name: FromStr, $name::Err: fmt::Display,)+
+        {
+            fn from_args(parsed: &ParsedCommand) -> Result<Self, Error> {
+                check_arity(parsed, $count)?;
+                let mut args = parsed.args.iter();
+                Ok(($(parse_arg::<$name>(args.next().expect("arity was checked"))?,)+))
+            }
+        }
+    };
+}
+
+tuple_from_args!(1; A);
+tuple_from_args!(2; A, B);
+tuple_from_args!(3; A, B, C);
+tuple_from_args!(4; A, B, C, D);
+tuple_from_args!(5; A, B, C, D, E);
+tuple_from_args!(6; A, B, C, D, E, F);
+
+#[cfg(test)]
+mod tests {
+    use crate::{Command, Error};
+
+    #[test]
+    fn parse_typed() {
+        let command = Command::new("/connect")
+            .arg("port", true)
+            .arg("server", false);
+
+        let (port, server): (u16, String) = command.parse_typed("/connect 6697 libera").unwrap();
+        assert_eq!(port, 6697);
+        assert_eq!(server, "libera");
+
+        assert!(matches!(
+            command.parse_typed::<(u16, String)>("/connect tls libera"),
+            Err(Error::InvalidArgValue { ref arg, .. }) if arg == "port"
+        ));
+        assert!(matches!(
+            command.parse_typed::<(u16, String)>("/connect 6697"),
+            Err(Error::TooFewArgs { min: 2, got: 1 })
+        ));
+        assert!(matches!(
+            command.parse_typed::<(u16,)>("/connect 6697 libera"),
+            Err(Error::TooManyArgs { max: 1, got: 2 })
+        ));
+    }
+}